@@ -0,0 +1,210 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    fs::File,
+    io::BufReader,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use log::{info, warn};
+use num_format::{Locale, ToFormattedString};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    device_info::DeviceInfo,
+    engine_error::EngineError,
+    engine_impl::EngineImpl,
+    function_impl::FunctionImpl,
+    gpu_engine::GpuEngine,
+    LOG_TARGET,
+};
+
+const GRID_SIZE_FACTORS: [f64; 5] = [0.25, 0.5, 1.0, 2.0, 4.0];
+const BLOCK_SIZE_FACTORS: [f64; 3] = [0.5, 1.0, 2.0];
+const NUM_ITERATIONS: [u32; 5] = [4, 8, 16, 32, 64];
+
+/// Best launch configuration found for a device
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct TunedLaunchConfiguration {
+    pub grid_size: u32,
+    pub block_size: u32,
+    pub num_iterations: u32,
+    pub hashes_per_second: u64,
+}
+
+/// Tuning results persisted between runs, keyed by backend, device name and driver version
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct TuningCache {
+    entries: BTreeMap<String, TunedLaunchConfiguration>,
+}
+
+impl TuningCache {
+    /// Loads the cache, returning an empty one if the file does not exist yet
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let file = File::open(path)?;
+        let reader = BufReader::new(file);
+        let cache = serde_json::from_reader(reader)?;
+        Ok(cache)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), anyhow::Error> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+
+    pub fn get(&self, device: &DeviceInfo) -> Option<TunedLaunchConfiguration> {
+        self.entries.get(&Self::key(device)).copied()
+    }
+
+    pub fn insert(&mut self, device: &DeviceInfo, tuned: TunedLaunchConfiguration) {
+        self.entries.insert(Self::key(device), tuned);
+    }
+
+    fn key(device: &DeviceInfo) -> String {
+        format!("{}|{}|{}", device.backend, device.device_name, device.driver_version)
+    }
+}
+
+/// Sweeps block size, grid size and `num_iterations` one after another against a synthetic job, keeping the value
+/// with the best sustained hashrate at each step.
+pub fn autotune_device<T: EngineImpl>(
+    gpu_engine: &GpuEngine<T>,
+    device: &DeviceInfo,
+    duration_per_candidate: Duration,
+) -> Result<TunedLaunchConfiguration, anyhow::Error> {
+    let context = gpu_engine.create_context(device.index)?;
+    let function = gpu_engine.get_main_function(&context)?;
+    let (suggested_grid_size, suggested_block_size) = function.suggested_launch_configuration()?;
+
+    let mut data = vec![0u64; 6];
+    for word in data.iter_mut().take(5).skip(1) {
+        *word = rand::random();
+    }
+    data[5] = u64::from_le_bytes([1, 0x06, 0, 0, 0, 0, 0, 0]);
+
+    let measure = |grid_size: u32, block_size: u32, num_iterations: u32| -> Result<u64, anyhow::Error> {
+        if grid_size
+            .checked_mul(block_size)
+            .and_then(|n| n.checked_mul(num_iterations))
+            .is_none()
+        {
+            return Ok(0);
+        }
+        // The first launch pays for compilation and allocation, so it is not measured
+        let mut nonce_start = rand::random::<u64>();
+        let result = match gpu_engine.mine(
+            &function,
+            &context,
+            &data,
+            0,
+            nonce_start,
+            num_iterations,
+            block_size,
+            grid_size,
+        ) {
+            Ok(result) => result,
+            // E.g. a work group larger than the kernel supports on this device
+            Err(EngineError::Launch(error)) => {
+                warn!(target: LOG_TARGET,
+                    "Device {}: skipping grid {} block {}: {}", device.index, grid_size, block_size, error
+                );
+                return Ok(0);
+            },
+            Err(error) => return Err(error.into()),
+        };
+        nonce_start = nonce_start.wrapping_add(result.hashes);
+        let mut total_hashes = 0u64;
+        let start = Instant::now();
+        while start.elapsed() < duration_per_candidate {
+            let result = gpu_engine.mine(
+                &function,
+                &context,
+                &data,
+                0,
+                nonce_start,
+                num_iterations,
+                block_size,
+                grid_size,
+            )?;
+            nonce_start = nonce_start.wrapping_add(result.hashes);
+            total_hashes += result.hashes;
+        }
+        // The next candidate changes the launch configuration, so the launches still in flight are finished here
+        for result in gpu_engine.flush(&context)? {
+            total_hashes += result.hashes;
+        }
+        let hashes_per_second = (total_hashes as f64 / start.elapsed().as_secs_f64()) as u64;
+        println!(
+            "Device {}: grid: {} block: {} iterations: {} hashes/sec: {}",
+            device.index,
+            grid_size,
+            block_size,
+            num_iterations,
+            hashes_per_second.to_formatted_string(&Locale::en)
+        );
+        info!(target: LOG_TARGET,
+            "Autotune device {}: grid: {} block: {} iterations: {} hashes/sec: {}",
+            device.index, grid_size, block_size, num_iterations, hashes_per_second
+        );
+        Ok(hashes_per_second)
+    };
+
+    let mut best = TunedLaunchConfiguration {
+        grid_size: suggested_grid_size,
+        block_size: suggested_block_size,
+        num_iterations: 16,
+        hashes_per_second: 0,
+    };
+
+    let max_block_size = u32::try_from(device.max_work_group_size).unwrap_or(u32::MAX);
+    let mut block_sizes: Vec<u32> = BLOCK_SIZE_FACTORS
+        .iter()
+        .map(|factor| scale(suggested_block_size, *factor))
+        .filter(|block_size| *block_size <= max_block_size)
+        .collect();
+    if !block_sizes.contains(&suggested_block_size) {
+        block_sizes.push(suggested_block_size);
+    }
+    for block_size in block_sizes {
+        let hashes_per_second = measure(best.grid_size, block_size, best.num_iterations)?;
+        if hashes_per_second > best.hashes_per_second {
+            best.block_size = block_size;
+            best.hashes_per_second = hashes_per_second;
+        }
+    }
+
+    for grid_size in GRID_SIZE_FACTORS.iter().map(|factor| scale(suggested_grid_size, *factor)) {
+        let hashes_per_second = measure(grid_size, best.block_size, best.num_iterations)?;
+        if hashes_per_second > best.hashes_per_second {
+            best.grid_size = grid_size;
+            best.hashes_per_second = hashes_per_second;
+        }
+    }
+
+    for num_iterations in NUM_ITERATIONS {
+        let hashes_per_second = measure(best.grid_size, best.block_size, num_iterations)?;
+        if hashes_per_second > best.hashes_per_second {
+            best.num_iterations = num_iterations;
+            best.hashes_per_second = hashes_per_second;
+        }
+    }
+
+    if best.hashes_per_second == 0 {
+        warn!(target: LOG_TARGET, "Autotune device {}: no candidate produced any hashes", device.index);
+        return Err(anyhow!("Autotune of device {} did not produce any hashes", device.index));
+    }
+    Ok(best)
+}
+
+fn scale(value: u32, factor: f64) -> u32 {
+    ((value as f64 * factor).round() as u32).max(1)
+}
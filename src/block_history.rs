@@ -0,0 +1,133 @@
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+/// Oldest submissions are dropped once the history holds this many
+const MAX_BLOCK_HISTORY: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SubmissionResult {
+    Accepted,
+    Rejected,
+    /// Rejected after a template for a later height had been fetched
+    Stale,
+}
+
+/// A block, or a p2pool share, submitted by one of the devices
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewBlockSubmission {
+    pub time: DateTime<Local>,
+    pub device_index: u32,
+    pub height: u64,
+    pub block_hash: String,
+    pub nonce: u64,
+    pub achieved_difficulty: u64,
+    pub target_difficulty: u64,
+    pub client_type: String,
+    pub result: SubmissionResult,
+    /// The node's response to an accepted block, the error otherwise
+    pub response: String,
+}
+
+/// A submission as recorded in the history
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockSubmission {
+    /// Assigned in submission order when the submission is recorded
+    pub id: u64,
+    #[serde(flatten)]
+    pub submission: NewBlockSubmission,
+}
+
+/// Bounded history of block submissions, newest last
+pub struct BlockHistory {
+    submissions: VecDeque<BlockSubmission>,
+    next_id: u64,
+}
+
+impl BlockHistory {
+    pub fn new() -> Self {
+        Self {
+            submissions: VecDeque::new(),
+            next_id: 0,
+        }
+    }
+
+    pub fn push(&mut self, submission: NewBlockSubmission) {
+        let id = self.next_id;
+        self.next_id += 1;
+        if self.submissions.len() == MAX_BLOCK_HISTORY {
+            self.submissions.pop_front();
+        }
+        self.submissions.push_back(BlockSubmission { id, submission });
+    }
+
+    pub fn count(&self) -> usize {
+        self.submissions.len()
+    }
+
+    /// Returns page `page` of `page_size` submissions, newest first
+    pub fn page(&self, page: usize, page_size: usize) -> Vec<BlockSubmission> {
+        self.submissions
+            .iter()
+            .rev()
+            .skip(page.saturating_mul(page_size))
+            .take(page_size)
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn submission(height: u64) -> NewBlockSubmission {
+        NewBlockSubmission {
+            time: Local::now(),
+            device_index: 0,
+            height,
+            block_hash: String::new(),
+            nonce: 0,
+            achieved_difficulty: 2,
+            target_difficulty: 1,
+            client_type: "base_node".to_string(),
+            result: SubmissionResult::Accepted,
+            response: String::new(),
+        }
+    }
+
+    fn ids(submissions: &[BlockSubmission]) -> Vec<u64> {
+        submissions.iter().map(|submission| submission.id).collect()
+    }
+
+    #[test]
+    fn pages_are_newest_first() {
+        let mut history = BlockHistory::new();
+        for height in 0..5 {
+            history.push(submission(height));
+        }
+        assert_eq!(history.count(), 5);
+        assert_eq!(ids(&history.page(0, 2)), vec![4, 3]);
+        assert_eq!(ids(&history.page(1, 2)), vec![2, 1]);
+        assert_eq!(ids(&history.page(2, 2)), vec![0]);
+        assert!(history.page(3, 2).is_empty());
+        assert!(history.page(usize::MAX, usize::MAX).is_empty());
+        assert_eq!(history.page(0, 1)[0].submission.height, 4);
+    }
+
+    #[test]
+    fn oldest_submissions_are_dropped() {
+        let mut history = BlockHistory::new();
+        for height in 0..MAX_BLOCK_HISTORY as u64 + 10 {
+            history.push(submission(height));
+        }
+        assert_eq!(history.count(), MAX_BLOCK_HISTORY);
+        let all = history.page(0, usize::MAX);
+        assert_eq!(all.len(), MAX_BLOCK_HISTORY);
+        assert_eq!(all.first().unwrap().id, MAX_BLOCK_HISTORY as u64 + 9);
+        // Ids keep counting past the dropped submissions
+        assert_eq!(all.last().unwrap().id, 10);
+    }
+}
@@ -1,12 +1,16 @@
 use std::{
+    collections::BTreeMap,
     fs::File,
     io::BufReader,
     path::{Path, PathBuf},
 };
 
-use anyhow;
+use anyhow::{self, anyhow};
+
+use crate::{duty_cycle::FULL_INTENSITY, engine_type::EngineType};
 
 #[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+#[serde(default)]
 pub(crate) struct ConfigFile {
     pub tari_address: String,
     pub tari_node_url: String,
@@ -15,7 +19,182 @@ pub(crate) struct ConfigFile {
     pub p2pool_enabled: bool,
     pub http_server_enabled: bool,
     pub http_server_port: u16,
+    /// Serve the `/control/*` routes that pause, resume and throttle mining
+    pub http_control_enabled: bool,
+    /// Bearer token the `/control/*` routes require. Without one they only accept requests from loopback
+    pub http_control_token: Option<String>,
     pub gpu_percentage: u16,
+    pub num_iterations: u32,
+    pub cpu_threads: Option<u32>,
+    /// Number of CUDA streams each device rotates its launches over. With two or more, the next batch is already
+    /// queued on the device while the host reads back the previous one
+    pub cuda_streams: usize,
+    pub engine: EngineType,
+    /// Devices to mine on, matched by index or device name substring. Empty means all devices
+    pub devices: Vec<String>,
+    /// Devices to leave idle, matched by index or device name substring
+    pub excluded_devices: Vec<String>,
+    /// Launch configuration overrides keyed by device index or device name substring
+    pub device_overrides: BTreeMap<String, DeviceOverride>,
+    /// Directory for files the miner generates itself, such as autotune results and compiled OpenCL programs.
+    /// Relative to the config file
+    pub cache_dir: PathBuf,
+    /// Keeps this rig's nonces apart from other rigs mining to the same address. Random per run if not set
+    pub rig_id: Option<u16>,
+    /// (Optional) OpenCL kernel source file to build instead of the built-in one. Relative to the config file
+    pub opencl_kernel_path: Option<PathBuf>,
+    /// Extra options passed to the OpenCL compiler
+    pub opencl_build_options: String,
+    /// Preprocessor defines passed to the OpenCL compiler as `-D`, e.g. `UNROLL=4`
+    pub opencl_defines: Vec<String>,
+    /// Name of the OpenCL kernel entry point
+    pub opencl_kernel_name: String,
+    pub thermal: ThermalConfig,
+    /// Decides when mining is active. Mining is always active by default
+    pub policy: MiningPolicyConfig,
+    /// A device that fails this many times in a row is disabled until the miner is restarted
+    pub max_device_failures: u32,
+    /// Wait before the first restart of a failed device, doubled for every further failure in a row
+    pub device_restart_backoff_secs: u64,
+    /// A batch running this many times longer than usual marks its device as hung
+    pub watchdog_timeout_multiplier: f64,
+    /// Batches are never considered hung before running this long
+    pub watchdog_min_timeout_secs: u64,
+    /// After a shutdown signal, the miner exits anyway if its devices haven't stopped within this time
+    pub shutdown_grace_period_secs: u64,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Default)]
+#[serde(default)]
+pub(crate) struct DeviceOverride {
+    pub grid_size: Option<u32>,
+    pub block_size: Option<u32>,
+    pub num_iterations: Option<u32>,
+    pub gpu_percentage: Option<u16>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+#[serde(default)]
+pub(crate) struct ThermalConfig {
+    /// Throttle devices that run above `target_celsius`, based on Linux hwmon sensors
+    pub enabled: bool,
+    /// Root of the sysfs tree the sensors are read from
+    pub sysfs_root: PathBuf,
+    pub target_celsius: f64,
+    /// How far below the target a device has to cool down before the throttle is eased
+    pub hysteresis_celsius: f64,
+    pub poll_interval_ms: u64,
+    /// Sensor of each device keyed by device index or device name substring, either a DRM card (`card1`) or a hwmon
+    /// device (`hwmon3`). Devices without an entry use `card<index>`
+    pub sensors: BTreeMap<String, String>,
+}
+
+impl Default for ThermalConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            sysfs_root: PathBuf::from("/sys"),
+            target_celsius: 80.0,
+            hysteresis_celsius: 5.0,
+            poll_interval_ms: 2000,
+            sensors: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+#[serde(default)]
+pub(crate) struct MiningPolicyConfig {
+    /// Daily local time windows to mine in. Empty means any time
+    pub time_windows: Vec<TimeWindowConfig>,
+    /// Only mine while a mains power supply is online
+    pub require_ac_power: bool,
+    /// Only mine while the one-minute load average is at or below this value
+    pub max_load_average: Option<f64>,
+    /// Root of the sysfs tree power supplies are read from
+    pub sysfs_root: PathBuf,
+    pub loadavg_path: PathBuf,
+    pub check_interval_secs: u64,
+}
+
+impl Default for MiningPolicyConfig {
+    fn default() -> Self {
+        Self {
+            time_windows: vec![],
+            require_ac_power: false,
+            max_load_average: None,
+            sysfs_root: PathBuf::from("/sys"),
+            loadavg_path: PathBuf::from("/proc/loadavg"),
+            check_interval_secs: 30,
+        }
+    }
+}
+
+/// Window from `start` to `end` as `HH:MM`, e.g. `22:00` to `07:00`
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+pub(crate) struct TimeWindowConfig {
+    pub start: String,
+    pub end: String,
+}
+
+impl ThermalConfig {
+    /// Sensor configured for the device. An index match wins over a name match
+    pub(crate) fn sensor(&self, index: u32, name: &str) -> Option<String> {
+        let index_match = self
+            .sensors
+            .iter()
+            .find(|(selector, _)| selector.trim().parse::<u32>() == Ok(index));
+        index_match
+            .or_else(|| {
+                self.sensors.iter().find(|(selector, _)| {
+                    selector.trim().parse::<u32>().is_err() && device_matches(selector, index, name)
+                })
+            })
+            .map(|(_, sensor)| sensor.clone())
+    }
+}
+
+impl DeviceOverride {
+    fn validate(&self) -> Result<(), anyhow::Error> {
+        let launch_values = [
+            ("grid_size", self.grid_size),
+            ("block_size", self.block_size),
+            ("num_iterations", self.num_iterations),
+        ];
+        let mut hashes_per_batch = 1u32;
+        for (name, value) in launch_values {
+            let Some(value) = value else {
+                continue;
+            };
+            if value == 0 {
+                return Err(anyhow!("{} must be greater than 0", name));
+            }
+            hashes_per_batch = hashes_per_batch
+                .checked_mul(value)
+                .ok_or_else(|| anyhow!("grid_size * block_size * num_iterations must fit in 32 bits"))?;
+        }
+        if let Some(gpu_percentage) = self.gpu_percentage {
+            if gpu_percentage == 0 || gpu_percentage > FULL_INTENSITY {
+                return Err(anyhow!("gpu_percentage must be in range 1-{}", FULL_INTENSITY));
+            }
+        }
+        Ok(())
+    }
+
+    fn merge(&mut self, other: &DeviceOverride) {
+        if other.grid_size.is_some() {
+            self.grid_size = other.grid_size;
+        }
+        if other.block_size.is_some() {
+            self.block_size = other.block_size;
+        }
+        if other.num_iterations.is_some() {
+            self.num_iterations = other.num_iterations;
+        }
+        if other.gpu_percentage.is_some() {
+            self.gpu_percentage = other.gpu_percentage;
+        }
+    }
 }
 
 impl Default for ConfigFile {
@@ -28,8 +207,31 @@ impl Default for ConfigFile {
             p2pool_enabled: false,
             http_server_enabled: true,
             http_server_port: 18000,
+            http_control_enabled: false,
+            http_control_token: None,
             // In range 1-1000
             gpu_percentage: 1000,
+            num_iterations: 16,
+            // Defaults to the number of available cores
+            cpu_threads: None,
+            cuda_streams: 2,
+            engine: EngineType::Auto,
+            devices: vec![],
+            excluded_devices: vec![],
+            device_overrides: BTreeMap::new(),
+            cache_dir: PathBuf::from("cache"),
+            rig_id: None,
+            opencl_kernel_path: None,
+            opencl_build_options: String::new(),
+            opencl_defines: vec![],
+            opencl_kernel_name: "sha3".to_string(),
+            thermal: ThermalConfig::default(),
+            policy: MiningPolicyConfig::default(),
+            max_device_failures: 5,
+            device_restart_backoff_secs: 2,
+            watchdog_timeout_multiplier: 10.0,
+            watchdog_min_timeout_secs: 30,
+            shutdown_grace_period_secs: 30,
         }
     }
 }
@@ -38,13 +240,78 @@ impl ConfigFile {
     pub(crate) fn load(path: &PathBuf) -> Result<Self, anyhow::Error> {
         let file = File::open(path)?;
         let reader = BufReader::new(file);
-        let config = serde_json::from_reader(reader)?;
+        let mut config: ConfigFile = serde_json::from_reader(reader)?;
+        config.validate()?;
+        config.resolve_paths(path);
         Ok(config)
     }
 
+    /// Makes relative paths relative to the directory of the config file rather than the working directory, which
+    /// is `/` for a service
+    pub(crate) fn resolve_paths(&mut self, config_path: &Path) {
+        let config_dir = config_path.parent().unwrap_or_else(|| Path::new(""));
+        self.cache_dir = config_dir.join(&self.cache_dir);
+        if let Some(kernel_path) = &self.opencl_kernel_path {
+            self.opencl_kernel_path = Some(config_dir.join(kernel_path));
+        }
+    }
+
+    /// Rejects launch settings that would leave a device without work or overflow the per-batch hash count
+    pub(crate) fn validate(&self) -> Result<(), anyhow::Error> {
+        if self.num_iterations == 0 {
+            return Err(anyhow!("num_iterations must be greater than 0"));
+        }
+        for (selector, device_override) in &self.device_overrides {
+            device_override
+                .validate()
+                .map_err(|error| anyhow!("Invalid device override {:?}: {}", selector, error))?;
+        }
+        Ok(())
+    }
+
     pub(crate) fn save(&self, path: &Path) -> Result<(), anyhow::Error> {
         let file = File::create(path)?;
         serde_json::to_writer_pretty(file, self)?;
         Ok(())
     }
+
+    pub(crate) fn tuning_cache_path(&self) -> PathBuf {
+        self.cache_dir.join("tuning.json")
+    }
+
+    pub(crate) fn opencl_binary_cache_dir(&self) -> PathBuf {
+        self.cache_dir.join("opencl")
+    }
+
+    /// Returns true if the device is allowed by `devices` and not excluded by `excluded_devices`
+    pub(crate) fn is_device_selected(&self, index: u32, name: &str) -> bool {
+        let allowed = self.devices.is_empty() || self.devices.iter().any(|entry| device_matches(entry, index, name));
+        allowed && !self.excluded_devices.iter().any(|entry| device_matches(entry, index, name))
+    }
+
+    /// Combines every override that matches the device. Name matches are applied first, so a more specific index
+    /// match wins.
+    pub(crate) fn device_override(&self, index: u32, name: &str) -> DeviceOverride {
+        let (index_overrides, name_overrides): (Vec<_>, Vec<_>) = self
+            .device_overrides
+            .iter()
+            .partition(|(selector, _)| selector.trim().parse::<u32>().is_ok());
+        let mut result = DeviceOverride::default();
+        for (selector, device_override) in name_overrides.into_iter().chain(index_overrides) {
+            if device_matches(selector, index, name) {
+                result.merge(device_override);
+            }
+        }
+        result
+    }
+}
+
+/// Matches a device selector against a device. A selector that parses as a number matches the device index,
+/// anything else is matched as a case-insensitive substring of the device name.
+fn device_matches(selector: &str, index: u32, name: &str) -> bool {
+    let selector = selector.trim();
+    match selector.parse::<u32>() {
+        Ok(selector_index) => selector_index == index,
+        Err(_) => name.to_lowercase().contains(&selector.to_lowercase()),
+    }
 }
@@ -0,0 +1,208 @@
+use std::{cmp, thread, time::Instant};
+
+use log::info;
+use sha3::{Digest, Sha3_256};
+
+use crate::{
+    context_impl::ContextImpl,
+    device_info::DeviceInfo,
+    engine_error::EngineError,
+    engine_impl::EngineImpl,
+    engine_type::EngineType,
+    function_impl::FunctionImpl,
+    mine_result::{MineResult, Solution, MAX_SOLUTIONS},
+    nonce_allocator::MAX_DEVICES_PER_RIG,
+    LOG_TARGET,
+};
+
+/// Pure-Rust reference engine. Every worker thread is exposed as a separate "device", so the mining loop can be run
+/// end to end on machines without a GPU.
+#[derive(Clone)]
+pub struct CpuEngine {
+    num_threads: u32,
+}
+
+impl CpuEngine {
+    pub fn new(num_threads: u32) -> Self {
+        Self {
+            num_threads: cmp::max(num_threads, 1),
+        }
+    }
+
+    /// Number of worker threads used when none are configured, one per core up to the devices a rig supports
+    pub fn default_num_threads() -> u32 {
+        let num_cores = thread::available_parallelism().map(|n| n.get() as u32).unwrap_or(1);
+        cmp::min(num_cores, MAX_DEVICES_PER_RIG)
+    }
+}
+
+impl EngineImpl for CpuEngine {
+    type Context = CpuContext;
+    type Function = CpuFunction;
+
+    fn init(&mut self) -> Result<(), EngineError> {
+        info!(target: LOG_TARGET, "CpuEngine: init engine with {} threads", self.num_threads);
+        Ok(())
+    }
+
+    fn num_devices(&self) -> Result<u32, EngineError> {
+        Ok(self.num_threads)
+    }
+
+    fn list_devices(&self) -> Result<Vec<DeviceInfo>, EngineError> {
+        Ok((0..self.num_threads)
+            .map(|index| DeviceInfo {
+                index,
+                backend: EngineType::Cpu,
+                platform_name: "CPU".to_string(),
+                device_name: format!("CPU worker {}", index),
+                vendor: std::env::consts::ARCH.to_string(),
+                driver_version: env!("CARGO_PKG_VERSION").to_string(),
+                compute_units: 1,
+                max_work_group_size: 1,
+                global_memory: 0,
+                launch_configuration: None,
+            })
+            .collect())
+    }
+
+    fn create_context(&self, device_index: u32) -> Result<Self::Context, EngineError> {
+        info!(target: LOG_TARGET, "CpuEngine: create context");
+        if device_index >= self.num_threads {
+            return Err(EngineError::DeviceNotFound(device_index));
+        }
+        Ok(CpuContext { device_index })
+    }
+
+    fn create_main_function(&self, context: &Self::Context) -> Result<Self::Function, EngineError> {
+        info!(target: LOG_TARGET, "CpuEngine: create function for thread {}", context.device_index);
+        Ok(CpuFunction {})
+    }
+
+    fn mine(
+        &self,
+        _function: &Self::Function,
+        _context: &Self::Context,
+        data: &[u64],
+        min_difficulty: u64,
+        nonce_start: u64,
+        num_iterations: u32,
+        block_size: u32,
+        grid_size: u32,
+    ) -> Result<MineResult, EngineError> {
+        let timer = Instant::now();
+        let num_hashes = u64::from(grid_size) * u64::from(block_size) * u64::from(num_iterations);
+        let mut solutions = vec![];
+        let mut best_hash = u64::MAX;
+        for i in 0..num_hashes {
+            let nonce = nonce_start.wrapping_add(i);
+            let hash = sha3x_hash(nonce, data);
+            let hash64 = u64::from_be_bytes([hash[0], hash[1], hash[2], hash[3], hash[4], hash[5], hash[6], hash[7]]);
+            if hash64 < best_hash {
+                best_hash = hash64;
+            }
+            if hash64 < min_difficulty && solutions.len() < MAX_SOLUTIONS {
+                solutions.push(Solution { nonce, hash: hash64 });
+            }
+        }
+        Ok(MineResult {
+            solutions,
+            hashes: num_hashes,
+            best_hash,
+            kernel_time: timer.elapsed(),
+        })
+    }
+}
+
+/// Computes the SHA3x triple hash over the `data` layout built by `run_thread`: `data[1..5]` hold the mining hash as
+/// little-endian words and the low byte of `data[5]` holds the PoW algorithm byte.
+fn sha3x_hash(nonce: u64, data: &[u64]) -> [u8; 32] {
+    let mut input = [0u8; 41];
+    input[0..8].copy_from_slice(&nonce.to_le_bytes());
+    for (i, word) in data[1..5].iter().enumerate() {
+        input[8 + i * 8..16 + i * 8].copy_from_slice(&word.to_le_bytes());
+    }
+    input[40] = data[5].to_le_bytes()[0];
+    let hash = Sha3_256::digest(input);
+    let hash = Sha3_256::digest(hash);
+    Sha3_256::digest(hash).into()
+}
+
+pub struct CpuContext {
+    device_index: u32,
+}
+
+impl ContextImpl for CpuContext {}
+
+pub struct CpuFunction {}
+
+impl FunctionImpl for CpuFunction {
+    fn suggested_launch_configuration(&self) -> Result<(u32, u32), EngineError> {
+        Ok((64, 256))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tari_core::{
+        blocks::BlockHeader,
+        proof_of_work::{sha3x_difficulty, Difficulty, PowAlgorithm},
+    };
+
+    use super::*;
+    use crate::{mine_result::DATA_WORDS, write_job_data};
+
+    fn job(nonce: u64) -> (BlockHeader, Vec<u64>) {
+        let mut header = BlockHeader::new(0);
+        header.height = 1234;
+        header.pow.pow_algo = PowAlgorithm::Sha3x;
+        header.nonce = nonce;
+        let mut data = vec![0u64; DATA_WORDS];
+        write_job_data(&mut data, &header.mining_hash());
+        (header, data)
+    }
+
+    #[test]
+    fn sha3x_hash_matches_tari_core() {
+        for nonce in [0, 1, 0xdead_beef, u64::MAX] {
+            let (header, data) = job(nonce);
+            let hash = sha3x_hash(nonce, &data);
+            assert_eq!(
+                Difficulty::big_endian_difficulty(&hash).unwrap(),
+                sha3x_difficulty(&header).unwrap(),
+                "nonce {}",
+                nonce
+            );
+        }
+    }
+
+    #[test]
+    fn one_nonce_mine_matches_tari_core() {
+        let nonce = 42;
+        let (header, data) = job(nonce);
+        let hash = sha3x_hash(nonce, &data);
+        let engine = CpuEngine::new(1);
+        let context = engine.create_context(0).unwrap();
+        let function = engine.create_main_function(&context).unwrap();
+
+        let result = engine
+            .mine(&function, &context, &data, u64::MAX, nonce, 1, 1, 1)
+            .unwrap();
+        assert_eq!(result.hashes, 1);
+        assert_eq!(result.best_hash, u64::from_be_bytes(hash[..8].try_into().unwrap()));
+        assert_eq!(result.solutions, vec![Solution {
+            nonce,
+            hash: result.best_hash
+        }]);
+        assert_eq!(
+            Difficulty::big_endian_difficulty(&hash).unwrap(),
+            sha3x_difficulty(&header).unwrap()
+        );
+
+        // A target the hash misses reports no solution
+        let result = engine
+            .mine(&function, &context, &data, result.best_hash, nonce, 1, 1, 1)
+            .unwrap();
+        assert!(result.solutions.is_empty());
+    }
+}
@@ -1,22 +1,35 @@
 use crate::context_impl::ContextImpl;
+use crate::device_info::DeviceInfo;
+use crate::engine_error::EngineError;
+use crate::engine_type::EngineType;
+use crate::mine_result::{MineResult, DATA_WORDS, OUTPUT_WORDS};
 use crate::EngineImpl;
 use crate::FunctionImpl;
-use anyhow::Error;
+use crate::LOG_TARGET;
 #[cfg(feature = "nvidia")]
 use cust::{
     device::DeviceAttribute,
-    memory::{AsyncCopyDestination, DeviceCopy},
+    error::CudaError,
+    event::{Event, EventFlags},
+    memory::{AsyncCopyDestination, DeviceCopy, LockedBuffer},
     module::{ModuleJitOption, ModuleJitOption::DetermineTargetFromContext},
     prelude::{Module, *},
+    CudaApiVersion,
 };
 
-use std::time::Instant;
+use log::warn;
+use std::{cmp, collections::VecDeque, sync::Mutex, time::Duration};
+
 #[derive(Clone)]
-pub struct CudaEngine {}
+pub struct CudaEngine {
+    num_streams: usize,
+}
 
 impl CudaEngine {
-    pub fn new() -> Self {
-        Self {}
+    pub fn new(num_streams: usize) -> Self {
+        Self {
+            num_streams: cmp::max(num_streams, 1),
+        }
     }
 }
 
@@ -24,28 +37,76 @@ impl EngineImpl for CudaEngine {
     type Context = CudaContext;
     type Function = CudaFunction;
 
-    fn init(&mut self) -> Result<(), anyhow::Error> {
-        cust::init(CudaFlags::empty())?;
+    fn init(&mut self) -> Result<(), EngineError> {
+        cust::init(CudaFlags::empty()).map_err(|e| EngineError::Init(e.to_string()))?;
         Ok(())
     }
 
-    fn num_devices(&self) -> Result<u32, anyhow::Error> {
-        let num_devices = Device::num_devices()?;
+    fn num_devices(&self) -> Result<u32, EngineError> {
+        let num_devices = Device::num_devices().map_err(device_query_error)?;
         Ok(num_devices)
     }
 
-    fn create_context(&self, device_index: u32) -> Result<Self::Context, anyhow::Error> {
-        let context = Context::new(Device::get_device(device_index)?)?;
-        context.set_flags(ContextFlags::SCHED_YIELD)?;
+    fn list_devices(&self) -> Result<Vec<DeviceInfo>, EngineError> {
+        let driver_version = CudaApiVersion::get()
+            .map(|version| format!("{}.{}", version.major(), version.minor()))
+            .unwrap_or_default();
+        let mut devices = vec![];
+        for index in 0..Device::num_devices().map_err(device_query_error)? {
+            let device = Device::get_device(index).map_err(device_query_error)?;
+            devices.push(DeviceInfo {
+                index,
+                backend: EngineType::Cuda,
+                platform_name: "CUDA".to_string(),
+                device_name: device.name().map_err(device_query_error)?,
+                vendor: "NVIDIA".to_string(),
+                driver_version: driver_version.clone(),
+                compute_units: device
+                    .get_attribute(DeviceAttribute::MultiprocessorCount)
+                    .map_err(device_query_error)? as u32,
+                max_work_group_size: device
+                    .get_attribute(DeviceAttribute::MaxThreadsPerBlock)
+                    .map_err(device_query_error)? as u64,
+                global_memory: device.total_memory().map_err(device_query_error)? as u64,
+                launch_configuration: None,
+            });
+        }
+        Ok(devices)
+    }
+
+    fn create_context(&self, device_index: u32) -> Result<Self::Context, EngineError> {
+        let device = Device::get_device(device_index).map_err(|_| EngineError::DeviceNotFound(device_index))?;
+        let context = Context::new(device).map_err(context_error)?;
+        context.set_flags(ContextFlags::SCHED_YIELD).map_err(context_error)?;
+
+        let mut slots = Vec::with_capacity(self.num_streams);
+        for _ in 0..self.num_streams {
+            slots.push(StreamSlot {
+                stream: Stream::new(StreamFlags::NON_BLOCKING, None).map_err(context_error)?,
+                data_buf: DeviceBuffer::zeroed(DATA_WORDS).map_err(context_error)?,
+                output_buf: DeviceBuffer::zeroed(OUTPUT_WORDS).map_err(context_error)?,
+                host_output: LockedBuffer::new(&0u64, OUTPUT_WORDS).map_err(context_error)?,
+                kernel_started: Event::new(EventFlags::DEFAULT).map_err(context_error)?,
+                kernel_finished: Event::new(EventFlags::DEFAULT).map_err(context_error)?,
+            });
+        }
 
-        Ok(CudaContext { context })
+        Ok(CudaContext {
+            pipeline: Mutex::new(Pipeline {
+                slots,
+                in_flight: VecDeque::new(),
+                next_slot: 0,
+            }),
+            context,
+        })
     }
 
-    fn create_main_function(&self, context: &Self::Context) -> Result<Self::Function, anyhow::Error> {
+    fn create_main_function(&self, context: &Self::Context) -> Result<Self::Function, EngineError> {
         let module = Module::from_ptx(
             include_str!("../cuda/keccak.ptx"),
             &[ModuleJitOption::GenerateLineInfo(true)],
-        )?;
+        )
+        .map_err(compile_error)?;
         // let func = context.module.get_function("keccakKernel")?;
         Ok(CudaFunction { module })
     }
@@ -60,65 +121,166 @@ impl EngineImpl for CudaEngine {
         num_iterations: u32,
         block_size: u32,
         grid_size: u32,
-    ) -> Result<(Option<u64>, u32, u64), Error> {
-        let output = vec![0u64; 5];
-        let mut output_buf = output.as_slice().as_dbuf()?;
+    ) -> Result<MineResult, EngineError> {
+        let func = function.module.get_function("keccakKernel").map_err(compile_error)?;
+        let mut pipeline = context.pipeline.lock().unwrap();
+        let mut job_data = [0u64; DATA_WORDS];
+        job_data.copy_from_slice(&data[..DATA_WORDS]);
+        let params = LaunchParams {
+            min_difficulty,
+            num_iterations,
+            block_size,
+            grid_size,
+        };
+
+        // Results of launches for another job or launch configuration can't be reported as this call's. Callers flush
+        // before changing either, so this only catches a caller that forgot to
+        if pipeline
+            .in_flight
+            .iter()
+            .any(|batch| batch.data != job_data || batch.params != params)
+        {
+            let discarded = pipeline.drain()?;
+            warn!(target: LOG_TARGET,
+                "CudaEngine: job changed without a flush, discarded {} launches", discarded.len()
+            );
+        }
 
-        let mut data_buf = data.as_dbuf()?;
-        data_buf.copy_from(data).expect("Could not copy data to buffer");
-        output_buf.copy_from(&output).expect("Could not copy output to buffer");
+        let slot_index = pipeline.next_slot;
+        let StreamSlot {
+            stream,
+            data_buf,
+            output_buf,
+            host_output,
+            kernel_started,
+            kernel_finished,
+        } = &mut pipeline.slots[slot_index];
+        host_output.fill(0);
+        host_output[1] = u64::MAX;
+        unsafe {
+            data_buf.async_copy_from(&job_data, stream).map_err(launch_error)?;
+            output_buf.async_copy_from(&*host_output, stream).map_err(launch_error)?;
+            kernel_started.record(stream).map_err(launch_error)?;
+            launch!(
+                func<<<grid_size, block_size, 0, stream>>>(
+                    data_buf.as_device_ptr(),
+                    nonce_start,
+                    min_difficulty,
+                    num_iterations,
+                    output_buf.as_device_ptr(),
+                )
+            )
+            .map_err(launch_error)?;
+            kernel_finished.record(stream).map_err(launch_error)?;
+            output_buf.async_copy_to(host_output, stream).map_err(readback_error)?;
+        }
+        pipeline.in_flight.push_back(InFlight {
+            slot: slot_index,
+            data: job_data,
+            params,
+        });
+        pipeline.next_slot = (slot_index + 1) % pipeline.slots.len();
 
-        let num_streams = 1;
-        let mut streams = Vec::with_capacity(num_streams);
-        let func = function.module.get_function("keccakKernel")?;
+        // Until every slot is busy nothing is read back, so the device always has the next launch queued while the
+        // host verifies and submits the results of the previous one
+        if pipeline.in_flight.len() < pipeline.slots.len() {
+            return Ok(MineResult {
+                solutions: vec![],
+                hashes: 0,
+                best_hash: u64::MAX,
+                kernel_time: Duration::ZERO,
+            });
+        }
+        let batch = pipeline.in_flight.pop_front().expect("pipeline holds a launch");
+        pipeline.read_back(&batch)
+    }
 
-        let output = vec![0u64; 5];
+    fn flush(&self, context: &Self::Context) -> Result<Vec<MineResult>, EngineError> {
+        context.pipeline.lock().unwrap().drain()
+    }
+}
 
-        for st in 0..num_streams {
-            let stream = Stream::new(StreamFlags::NON_BLOCKING, None)?;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct LaunchParams {
+    min_difficulty: u64,
+    num_iterations: u32,
+    block_size: u32,
+    grid_size: u32,
+}
 
-            streams.push(stream);
-        }
+impl LaunchParams {
+    fn hashes(&self) -> u64 {
+        u64::from(self.grid_size) * u64::from(self.block_size) * u64::from(self.num_iterations)
+    }
+}
 
-        let data_ptr = data_buf.as_device_ptr();
-        for st in 0..num_streams {
-            let stream = &streams[st];
-            unsafe {
-                launch!(
-                    func<<<grid_size, block_size, 0, stream>>>(
-                    data_ptr,
-                         nonce_start,
-                         min_difficulty,
-                         num_iterations,
-                         output_buf.as_device_ptr(),
-
-                    )
-                )?;
-            }
-        }
+/// A launch whose results have not been read back yet
+struct InFlight {
+    slot: usize,
+    data: [u64; DATA_WORDS],
+    params: LaunchParams,
+}
 
-        for st in 0..num_streams {
-            let mut out1 = vec![0u64; 5];
+/// Launches rotate over the stream slots, so one batch runs on the device while the host handles the previous one
+struct Pipeline {
+    slots: Vec<StreamSlot>,
+    /// Oldest first
+    in_flight: VecDeque<InFlight>,
+    next_slot: usize,
+}
 
-            unsafe {
-                output_buf.copy_to(&mut out1)?;
-            }
-            //stream.synchronize()?;
+impl Pipeline {
+    /// Waits for `batch` and reads its results. The kernel time is measured on the device, so it doesn't include the
+    /// time the launch spent queued behind the previous one.
+    fn read_back(&self, batch: &InFlight) -> Result<MineResult, EngineError> {
+        let slot = &self.slots[batch.slot];
+        slot.stream.synchronize().map_err(readback_error)?;
+        let kernel_millis = slot
+            .kernel_finished
+            .elapsed_time_f32(&slot.kernel_started)
+            .map_err(readback_error)?;
+        Ok(MineResult::from_kernel_output(
+            &slot.host_output,
+            batch.params.hashes(),
+            Duration::from_secs_f32(kernel_millis.max(0.0) / 1000.0),
+        ))
+    }
 
-            if out1[0] > 0 {
-                return Ok((Some((&out1[0]).clone()), grid_size * block_size * num_iterations, 0));
-            }
+    /// Waits for every launch in flight and returns its results, oldest first
+    fn drain(&mut self) -> Result<Vec<MineResult>, EngineError> {
+        let mut results = Vec::with_capacity(self.in_flight.len());
+        while let Some(batch) = self.in_flight.pop_front() {
+            results.push(self.read_back(&batch)?);
         }
-
-        Ok((None, grid_size * block_size * num_iterations, 0))
+        Ok(results)
     }
 }
 
+/// Per-stream device buffers and pinned host memory for reading results back
+struct StreamSlot {
+    stream: Stream,
+    data_buf: DeviceBuffer<u64>,
+    output_buf: DeviceBuffer<u64>,
+    host_output: LockedBuffer<u64>,
+    /// Recorded around the kernel, so its run time can be measured on the device
+    kernel_started: Event,
+    kernel_finished: Event,
+}
+
 pub struct CudaContext {
+    // Dropped before the context they were allocated in
+    pipeline: Mutex<Pipeline>,
     context: Context,
 }
 
-impl CudaContext {}
+impl Drop for CudaContext {
+    fn drop(&mut self) {
+        // Launches still in flight copy into the pinned host buffers, which must not be freed under them
+        if let Ok(pipeline) = self.pipeline.get_mut() {
+            let _ = pipeline.drain();
+        }
+    }
+}
 
 impl ContextImpl for CudaContext {}
 
@@ -126,10 +288,35 @@ pub struct CudaFunction {
     module: Module,
 }
 impl FunctionImpl for CudaFunction {
-    fn suggested_launch_configuration(&self) -> Result<(u32, u32), anyhow::Error> {
-        let func = self.module.get_function("keccakKernel")?;
-        let (grid_size, block_size) = func.suggested_launch_configuration(0, 0.into())?;
+    fn suggested_launch_configuration(&self) -> Result<(u32, u32), EngineError> {
+        let func = self.module.get_function("keccakKernel").map_err(compile_error)?;
+        let (grid_size, block_size) = func
+            .suggested_launch_configuration(0, 0.into())
+            .map_err(device_query_error)?;
         // Ok((grid_size, block_size))
         Ok((1000, 100))
     }
 }
+
+fn device_query_error(error: CudaError) -> EngineError {
+    EngineError::DeviceQuery(error.to_string())
+}
+
+fn context_error(error: CudaError) -> EngineError {
+    EngineError::Context(error.to_string())
+}
+
+fn compile_error(error: CudaError) -> EngineError {
+    EngineError::Compile {
+        message: error.to_string(),
+        build_log: String::new(),
+    }
+}
+
+fn launch_error(error: CudaError) -> EngineError {
+    EngineError::Launch(error.to_string())
+}
+
+fn readback_error(error: CudaError) -> EngineError {
+    EngineError::Readback(error.to_string())
+}
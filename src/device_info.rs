@@ -0,0 +1,52 @@
+use std::fmt::{Display, Formatter};
+
+use serde::{Deserialize, Serialize};
+
+use crate::engine_type::EngineType;
+
+/// Static description of a single device reported by an engine
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceInfo {
+    pub index: u32,
+    pub backend: EngineType,
+    pub platform_name: String,
+    pub device_name: String,
+    pub vendor: String,
+    pub driver_version: String,
+    pub compute_units: u32,
+    pub max_work_group_size: u64,
+    /// Global memory in bytes
+    pub global_memory: u64,
+    /// Launch configuration `FunctionImpl::suggested_launch_configuration` picks for this device, if it could be
+    /// determined
+    pub launch_configuration: Option<LaunchConfiguration>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct LaunchConfiguration {
+    pub grid_size: u32,
+    pub block_size: u32,
+}
+
+impl Display for DeviceInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[{}] {} {} | platform: {} | vendor: {} | driver: {} | compute units: {} | max work group size: {} | \
+             global memory: {} MiB",
+            self.index,
+            self.backend,
+            self.device_name,
+            self.platform_name,
+            self.vendor,
+            self.driver_version,
+            self.compute_units,
+            self.max_work_group_size,
+            self.global_memory / (1024 * 1024)
+        )?;
+        match self.launch_configuration {
+            Some(launch) => write!(f, " | grid: {} block: {}", launch.grid_size, launch.block_size),
+            None => write!(f, " | grid: - block: -"),
+        }
+    }
+}
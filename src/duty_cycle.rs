@@ -0,0 +1,110 @@
+use std::{
+    cmp,
+    time::{Duration, Instant},
+};
+
+/// `gpu_percentage` value that keeps the device busy all the time
+pub const FULL_INTENSITY: u16 = 1000;
+
+/// Busy time is balanced against wall-clock time over windows of this length
+const WINDOW: Duration = Duration::from_secs(10);
+
+/// Time-based intensity control. Batches always run at full size and the device idles between them, so a
+/// `gpu_percentage` of 500 keeps it busy half of the wall-clock time without making each launch less efficient.
+pub struct DutyCycle {
+    /// In range 1-1000
+    percentage: u16,
+    window_start: Instant,
+    busy: Duration,
+}
+
+impl DutyCycle {
+    pub fn new(percentage: u16) -> Self {
+        Self {
+            percentage: clamp_percentage(percentage),
+            window_start: Instant::now(),
+            busy: Duration::ZERO,
+        }
+    }
+
+    pub fn percentage(&self) -> u16 {
+        self.percentage
+    }
+
+    pub fn set_percentage(&mut self, percentage: u16) {
+        self.percentage = clamp_percentage(percentage);
+        self.restart_window();
+    }
+
+    /// Starts balancing busy time from now on. Called when mining resumes, so time the device sat idle for other
+    /// reasons isn't counted as idle time the duty cycle already owes.
+    pub fn restart_window(&mut self) {
+        self.window_start = Instant::now();
+        self.busy = Duration::ZERO;
+    }
+
+    /// Records a batch that kept the device busy for `kernel_time` and returns how long to idle before the next one
+    pub fn pause(&mut self, kernel_time: Duration) -> Duration {
+        if self.percentage >= FULL_INTENSITY {
+            return Duration::ZERO;
+        }
+        if self.window_start.elapsed() > WINDOW {
+            self.window_start = Instant::now().checked_sub(kernel_time).unwrap_or_else(Instant::now);
+            self.busy = Duration::ZERO;
+        }
+        self.busy += kernel_time;
+        let target_elapsed = self.busy * u32::from(FULL_INTENSITY) / u32::from(self.percentage);
+        target_elapsed.saturating_sub(self.window_start.elapsed())
+    }
+}
+
+fn clamp_percentage(percentage: u16) -> u16 {
+    cmp::max(cmp::min(percentage, FULL_INTENSITY), 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_intensity_never_pauses() {
+        let mut duty_cycle = DutyCycle::new(FULL_INTENSITY);
+        for _ in 0..5 {
+            assert_eq!(duty_cycle.pause(Duration::from_millis(100)), Duration::ZERO);
+        }
+    }
+
+    #[test]
+    fn half_intensity_idles_as_long_as_it_is_busy() {
+        let mut duty_cycle = DutyCycle::new(500);
+        // 100ms busy at 50% needs 200ms of wall-clock time, of which almost none has passed yet
+        let pause = duty_cycle.pause(Duration::from_millis(100));
+        assert!(pause <= Duration::from_millis(200), "{:?}", pause);
+        assert!(pause > Duration::from_millis(150), "{:?}", pause);
+        // The second batch is measured against the same window
+        let pause = duty_cycle.pause(Duration::from_millis(100));
+        assert!(pause <= Duration::from_millis(400), "{:?}", pause);
+        assert!(pause > Duration::from_millis(350), "{:?}", pause);
+    }
+
+    #[test]
+    fn idle_time_before_a_restart_is_not_credited() {
+        let mut duty_cycle = DutyCycle::new(500);
+        // E.g. a pause or a template fetch
+        std::thread::sleep(Duration::from_millis(300));
+        assert_eq!(duty_cycle.pause(Duration::from_millis(100)), Duration::ZERO);
+
+        duty_cycle.restart_window();
+        let pause = duty_cycle.pause(Duration::from_millis(100));
+        assert!(pause > Duration::from_millis(150), "{:?}", pause);
+    }
+
+    #[test]
+    fn percentage_is_clamped() {
+        assert_eq!(DutyCycle::new(0).percentage(), 1);
+        assert_eq!(DutyCycle::new(FULL_INTENSITY + 1).percentage(), FULL_INTENSITY);
+        let mut duty_cycle = DutyCycle::new(500);
+        duty_cycle.set_percentage(u16::MAX);
+        assert_eq!(duty_cycle.percentage(), FULL_INTENSITY);
+    }
+}
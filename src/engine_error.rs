@@ -0,0 +1,30 @@
+use thiserror::Error;
+
+/// Errors returned by an [`EngineImpl`](crate::engine_impl::EngineImpl) and its functions
+#[derive(Error, Debug)]
+pub enum EngineError {
+    #[error("Engine init failed: {0}")]
+    Init(String),
+    #[error("Device {0} not found")]
+    DeviceNotFound(u32),
+    #[error("Device query failed: {0}")]
+    DeviceQuery(String),
+    #[error("Could not create context: {0}")]
+    Context(String),
+    #[error("Kernel compile failed: {message}\n{build_log}")]
+    Compile { message: String, build_log: String },
+    #[error("Kernel launch failed: {0}")]
+    Launch(String),
+    #[error("Reading kernel output failed: {0}")]
+    Readback(String),
+    #[error("Function or context belongs to another engine: {0}")]
+    EngineMismatch(String),
+}
+
+impl EngineError {
+    /// Whether the failed call can be retried with the same context and function. Anything else means the device has
+    /// to be set up again or given up on.
+    pub fn is_recoverable(&self) -> bool {
+        matches!(self, EngineError::Launch(_) | EngineError::Readback(_))
+    }
+}
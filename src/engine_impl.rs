@@ -1,16 +1,25 @@
-use crate::{context_impl::ContextImpl, function_impl::FunctionImpl};
+use crate::{
+    context_impl::ContextImpl, device_info::DeviceInfo, engine_error::EngineError, function_impl::FunctionImpl,
+    mine_result::MineResult,
+};
 
 pub trait EngineImpl {
     type Context: ContextImpl;
     type Function: FunctionImpl;
-    fn init(&mut self) -> Result<(), anyhow::Error>;
+    fn init(&mut self) -> Result<(), EngineError>;
 
-    fn num_devices(&self) -> Result<u32, anyhow::Error>;
+    fn num_devices(&self) -> Result<u32, EngineError>;
 
-    fn create_context(&self, device_index: u32) -> Result<Self::Context, anyhow::Error>;
+    fn list_devices(&self) -> Result<Vec<DeviceInfo>, EngineError>;
 
-    fn create_main_function(&self, context: &Self::Context) -> Result<Self::Function, anyhow::Error>;
+    fn create_context(&self, device_index: u32) -> Result<Self::Context, EngineError>;
 
+    fn create_main_function(&self, context: &Self::Context) -> Result<Self::Function, EngineError>;
+
+    /// Mines `grid_size * block_size * num_iterations` nonces from `nonce_start`. A pipelined engine may return the
+    /// results of an earlier call with the same `data` and launch configuration instead, or an empty result while its
+    /// pipeline fills up, so solutions must be checked by their own nonce. Callers [`flush`](Self::flush) before
+    /// changing `data` or the launch configuration, otherwise launches still in flight are discarded.
     fn mine(
         &self,
         function: &Self::Function,
@@ -21,5 +30,11 @@ pub trait EngineImpl {
         num_iterations: u32,
         block_size: u32,
         grid_size: u32,
-    ) -> Result<(Option<u64>, u32, u64), anyhow::Error>;
+    ) -> Result<MineResult, EngineError>;
+
+    /// Waits for the launches a pipelined engine still has in flight and returns their results, oldest first. The
+    /// device is idle afterwards.
+    fn flush(&self, _context: &Self::Context) -> Result<Vec<MineResult>, EngineError> {
+        Ok(vec![])
+    }
 }
@@ -0,0 +1,29 @@
+use std::fmt::{Display, Formatter};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// Mining backend selected at runtime
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum EngineType {
+    /// Probe every compiled backend and use the first one that reports devices
+    #[default]
+    Auto,
+    Cuda,
+    #[value(name = "opencl")]
+    #[serde(rename = "opencl")]
+    OpenCl,
+    Cpu,
+}
+
+impl Display for EngineType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EngineType::Auto => write!(f, "auto"),
+            EngineType::Cuda => write!(f, "cuda"),
+            EngineType::OpenCl => write!(f, "opencl"),
+            EngineType::Cpu => write!(f, "cpu"),
+        }
+    }
+}
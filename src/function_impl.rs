@@ -1,3 +1,5 @@
+use crate::engine_error::EngineError;
+
 pub trait FunctionImpl {
-    fn suggested_launch_configuration(&self) -> Result<(u32, u32), anyhow::Error>;
+    fn suggested_launch_configuration(&self) -> Result<(u32, u32), EngineError>;
 }
@@ -1,4 +1,4 @@
-use crate::engine_impl::EngineImpl;
+use crate::{device_info::DeviceInfo, engine_error::EngineError, engine_impl::EngineImpl, mine_result::MineResult};
 
 #[derive(Clone)]
 pub struct GpuEngine<TEngineImpl: EngineImpl> {
@@ -10,19 +10,23 @@ impl<TEngineImpl: EngineImpl> GpuEngine<TEngineImpl> {
         GpuEngine { inner: engine }
     }
 
-    pub fn init(&mut self) -> Result<(), anyhow::Error> {
+    pub fn init(&mut self) -> Result<(), EngineError> {
         self.inner.init()
     }
 
-    pub fn num_devices(&self) -> Result<u32, anyhow::Error> {
+    pub fn num_devices(&self) -> Result<u32, EngineError> {
         self.inner.num_devices()
     }
 
-    pub fn create_context(&self, device_index: u32) -> Result<TEngineImpl::Context, anyhow::Error> {
+    pub fn list_devices(&self) -> Result<Vec<DeviceInfo>, EngineError> {
+        self.inner.list_devices()
+    }
+
+    pub fn create_context(&self, device_index: u32) -> Result<TEngineImpl::Context, EngineError> {
         self.inner.create_context(device_index)
     }
 
-    pub fn get_main_function(&self, context: &TEngineImpl::Context) -> Result<TEngineImpl::Function, anyhow::Error> {
+    pub fn get_main_function(&self, context: &TEngineImpl::Context) -> Result<TEngineImpl::Function, EngineError> {
         self.inner.create_main_function(context)
         // match self {
         //     GpuEngine::Cuda => {
@@ -50,7 +54,7 @@ impl<TEngineImpl: EngineImpl> GpuEngine<TEngineImpl> {
         num_iterations: u32,
         block_size: u32,
         grid_size: u32,
-    ) -> Result<(Option<u64>, u32, u64), anyhow::Error> {
+    ) -> Result<MineResult, EngineError> {
         self.inner.mine(
             function,
             context,
@@ -62,4 +66,8 @@ impl<TEngineImpl: EngineImpl> GpuEngine<TEngineImpl> {
             grid_size,
         )
     }
+
+    pub fn flush(&self, context: &TEngineImpl::Context) -> Result<Vec<MineResult>, EngineError> {
+        self.inner.flush(context)
+    }
 }
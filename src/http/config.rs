@@ -1,15 +1,27 @@
 pub struct Config {
     pub port: u16,
+    /// Serve the `/control/*` routes that change mining at runtime
+    pub control_enabled: bool,
+    /// Bearer token the `/control/*` routes require. Without one they only accept requests from loopback
+    pub control_token: Option<String>,
 }
 
 impl Default for Config {
     fn default() -> Self {
-        Self { port: 18000 }
+        Self {
+            port: 18000,
+            control_enabled: false,
+            control_token: None,
+        }
     }
 }
 
 impl Config {
-    pub fn new(port: u16) -> Self {
-        Self { port }
+    pub fn new(port: u16, control_enabled: bool, control_token: Option<String>) -> Self {
+        Self {
+            port,
+            control_enabled,
+            control_token,
+        }
     }
 }
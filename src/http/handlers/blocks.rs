@@ -0,0 +1,47 @@
+// Copyright 2024 The Tari Project
+// SPDX-License-Identifier: BSD-3-Clause
+
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+
+use crate::block_history::BlockSubmission;
+use crate::http::server::AppState;
+
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 500;
+
+#[derive(Deserialize)]
+pub struct BlocksQuery {
+    /// Zero-based, page 0 holds the newest submissions
+    pub page: Option<usize>,
+    pub page_size: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BlocksPage {
+    /// Number of submissions in the history
+    pub total: usize,
+    pub page: usize,
+    pub page_size: usize,
+    pub blocks: Vec<BlockSubmission>,
+}
+
+pub async fn handle_get_blocks(
+    State(state): State<AppState>,
+    Query(query): Query<BlocksQuery>,
+) -> Result<Json<BlocksPage>, StatusCode> {
+    let page = query.page.unwrap_or(0);
+    let page_size = query.page_size.unwrap_or(DEFAULT_PAGE_SIZE);
+    if page_size == 0 || page_size > MAX_PAGE_SIZE {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let (total, blocks) = state.stats_store.block_submissions(page, page_size);
+    Ok(Json(BlocksPage {
+        total,
+        page,
+        page_size,
+        blocks,
+    }))
+}
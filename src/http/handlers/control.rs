@@ -0,0 +1,66 @@
+// Copyright 2024 The Tari Project
+// SPDX-License-Identifier: BSD-3-Clause
+
+use std::net::SocketAddr;
+
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::Next;
+use axum::response::Response;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+
+use crate::duty_cycle::FULL_INTENSITY;
+use crate::http::server::AppState;
+use crate::mining_control::ControlState;
+
+#[derive(Serialize, Deserialize)]
+pub struct IntensityRequest {
+    /// In range 1-1000, where 500 = busy 50% of the wall-clock time. `null` restores the configured value
+    pub gpu_percentage: Option<u16>,
+}
+
+/// Lets a control request through if it carries the configured bearer token, or, without a token configured, if it
+/// comes from loopback
+pub async fn authorize(
+    State(token): State<Option<String>>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let authorized = match &token {
+        Some(token) => {
+            request
+                .headers()
+                .get(header::AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("Bearer "))
+                == Some(token.as_str())
+        },
+        None => address.ip().is_loopback(),
+    };
+    if !authorized {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    Ok(next.run(request).await)
+}
+
+pub async fn handle_pause(State(state): State<AppState>) -> Result<Json<ControlState>, StatusCode> {
+    Ok(Json(state.mining_control.pause()))
+}
+
+pub async fn handle_resume(State(state): State<AppState>) -> Result<Json<ControlState>, StatusCode> {
+    Ok(Json(state.mining_control.resume()))
+}
+
+pub async fn handle_intensity(
+    State(state): State<AppState>,
+    Json(request): Json<IntensityRequest>,
+) -> Result<Json<ControlState>, StatusCode> {
+    if let Some(gpu_percentage) = request.gpu_percentage {
+        if gpu_percentage == 0 || gpu_percentage > FULL_INTENSITY {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+    }
+    Ok(Json(state.mining_control.set_gpu_percentage(request.gpu_percentage)))
+}
@@ -1,8 +1,16 @@
 // Copyright 2024 The Tari Project
 // SPDX-License-Identifier: BSD-3-Clause
 
+use axum::extract::State;
 use axum::http::StatusCode;
 
-pub async fn handle_health() -> Result<StatusCode, StatusCode> {
-    Ok(StatusCode::OK)
+use crate::http::server::AppState;
+
+/// Fails with 503 while the watchdog considers any device hung
+pub async fn handle_health(State(state): State<AppState>) -> Result<StatusCode, StatusCode> {
+    if state.stats_store.unhealthy_devices().is_empty() {
+        Ok(StatusCode::OK)
+    } else {
+        Err(StatusCode::SERVICE_UNAVAILABLE)
+    }
 }
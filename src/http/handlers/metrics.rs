@@ -0,0 +1,136 @@
+// Copyright 2024 The Tari Project
+// SPDX-License-Identifier: BSD-3-Clause
+
+use std::fmt::Write;
+
+use axum::extract::State;
+use axum::http::{header, StatusCode};
+
+use crate::http::server::AppState;
+use crate::stats_store::{DeviceStats, Latency};
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Serves the stats in the Prometheus text exposition format
+pub async fn handle_metrics(
+    State(state): State<AppState>,
+) -> Result<([(header::HeaderName, &'static str); 1], String), StatusCode> {
+    let stats_store = &state.stats_store;
+    let mut metrics = Metrics::default();
+
+    metrics.family("glytex_uptime_seconds", "gauge", "Seconds since the miner started");
+    metrics.sample("glytex_uptime_seconds", &[], stats_store.uptime().as_secs_f64());
+
+    let device_stats = stats_store.device_stats();
+    metrics.family(
+        "glytex_device_hashrate",
+        "gauge",
+        "Hashes per second of a device, current or averaged over a window",
+    );
+    for (device_index, stats) in &device_stats {
+        let device = device_index.to_string();
+        for (window, value) in [
+            ("current", stats.hashes_per_second),
+            ("10s", stats.hashrate.last_10s),
+            ("60s", stats.hashrate.last_60s),
+            ("15m", stats.hashrate.last_15m),
+            ("session", stats.hashrate.session_average),
+        ] {
+            metrics.sample(
+                "glytex_device_hashrate",
+                &[("device", &device), ("window", window)],
+                value as f64,
+            );
+        }
+    }
+    let device_counters: [(&str, &str, fn(&DeviceStats) -> u64); 4] = [
+        ("glytex_device_hashes_total", "Hashes computed by a device", |stats| {
+            stats.hashes
+        }),
+        (
+            "glytex_device_solutions_total",
+            "Verified solutions found by a device",
+            |stats| stats.solutions,
+        ),
+        (
+            "glytex_device_invalid_solutions_total",
+            "Solutions from a device that failed host-side verification",
+            |stats| stats.invalid_solutions,
+        ),
+        ("glytex_device_errors_total", "Engine errors of a device", |stats| {
+            stats.errors
+        }),
+    ];
+    for (name, help, value) in device_counters {
+        metrics.family(name, "counter", help);
+        for (device_index, stats) in &device_stats {
+            metrics.sample(name, &[("device", &device_index.to_string())], value(stats) as f64);
+        }
+    }
+
+    metrics.family("glytex_blocks_total", "counter", "Submitted blocks by result");
+    for (result, value) in [
+        ("accepted", stats_store.accepted_blocks()),
+        ("rejected", stats_store.rejected_blocks()),
+        ("stale", stats_store.stale_blocks()),
+    ] {
+        metrics.sample("glytex_blocks_total", &[("result", result)], value as f64);
+    }
+
+    metrics.latency(
+        "glytex_template_fetch_seconds",
+        "Time taken to fetch a block template",
+        stats_store.template_fetch_latency(),
+    );
+    metrics.latency(
+        "glytex_submit_seconds",
+        "Time taken to submit a block",
+        stats_store.submit_latency(),
+    );
+
+    metrics.family(
+        "glytex_target_difficulty",
+        "gauge",
+        "Target difficulty of the latest template",
+    );
+    metrics.sample("glytex_target_difficulty", &[], stats_store.target_difficulty() as f64);
+    metrics.family("glytex_block_height", "gauge", "Height of the newest template");
+    metrics.sample("glytex_block_height", &[], stats_store.block_height() as f64);
+
+    Ok(([(header::CONTENT_TYPE, CONTENT_TYPE)], metrics.output))
+}
+
+#[derive(Default)]
+struct Metrics {
+    output: String,
+}
+
+impl Metrics {
+    fn family(&mut self, name: &str, metric_type: &str, help: &str) {
+        let _ = writeln!(self.output, "# HELP {} {}", name, help);
+        let _ = writeln!(self.output, "# TYPE {} {}", name, metric_type);
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        self.output.push_str(name);
+        if !labels.is_empty() {
+            let labels = labels
+                .iter()
+                .map(|(label, value)| format!("{}=\"{}\"", label, escape_label_value(value)))
+                .collect::<Vec<_>>()
+                .join(",");
+            let _ = write!(self.output, "{{{}}}", labels);
+        }
+        let _ = writeln!(self.output, " {}", value);
+    }
+
+    fn latency(&mut self, name: &str, help: &str, latency: Latency) {
+        self.family(name, "summary", help);
+        self.sample(&format!("{}_sum", name), &[], latency.total.as_secs_f64());
+        self.sample(&format!("{}_count", name), &[], latency.count as f64);
+    }
+}
+
+fn escape_label_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
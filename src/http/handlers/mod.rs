@@ -1,4 +1,7 @@
+pub mod blocks;
+pub mod control;
 pub mod health;
+pub mod metrics;
 
 pub mod stats;
 pub mod version;
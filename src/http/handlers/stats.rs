@@ -1,4 +1,11 @@
+use std::collections::BTreeMap;
+
 use crate::http::server::AppState;
+use crate::mining_control::ControlState;
+use crate::mining_policy::PolicyDecision;
+use crate::stats_store::{DeviceStats, RollingHashrate};
+use crate::supervisor::DeviceStatus;
+use crate::thermal::ThermalReading;
 use axum::extract::State;
 use axum::http::StatusCode;
 use axum::Json;
@@ -6,15 +13,35 @@ use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize)]
 pub struct Stats {
+    /// Sum across all devices
     pub hashes_per_second: u64,
+    /// Sum across all devices
+    pub hashrate: RollingHashrate,
     pub accepted_blocks: u64,
     pub rejected_blocks: u64,
+    pub stale_blocks: u64,
+    pub invalid_solutions: BTreeMap<u32, u64>,
+    pub device_stats: BTreeMap<u32, DeviceStats>,
+    pub thermal: BTreeMap<u32, ThermalReading>,
+    pub control: ControlState,
+    pub policy: PolicyDecision,
+    pub devices: BTreeMap<u32, DeviceStatus>,
+    pub unhealthy_devices: Vec<u32>,
 }
 
 pub async fn handle_get_stats(State(state): State<AppState>) -> Result<Json<Stats>, StatusCode> {
     Ok(Json(Stats {
         hashes_per_second: state.stats_store.hashes_per_second(),
+        hashrate: state.stats_store.hashrate(),
         accepted_blocks: state.stats_store.accepted_blocks(),
         rejected_blocks: state.stats_store.rejected_blocks(),
+        stale_blocks: state.stats_store.stale_blocks(),
+        invalid_solutions: state.stats_store.invalid_solutions(),
+        device_stats: state.stats_store.device_stats(),
+        thermal: state.stats_store.thermal(),
+        control: state.mining_control.state(),
+        policy: state.stats_store.policy(),
+        devices: state.stats_store.devices(),
+        unhealthy_devices: state.stats_store.unhealthy_devices(),
     }))
 }
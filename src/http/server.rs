@@ -1,18 +1,29 @@
-use crate::http::config;
-use crate::http::handlers::{health, stats, version};
-use crate::stats_store::StatsStore;
-use axum::routing::get;
-use axum::Router;
-use std::sync::Arc;
+use std::{net::SocketAddr, sync::Arc};
+
+use axum::{
+    middleware,
+    routing::{get, post},
+    Router,
+};
 use tari_shutdown::ShutdownSignal;
 use thiserror::Error;
 use tokio::io;
 
+use crate::{
+    http::{
+        config,
+        handlers::{blocks, control, health, metrics, stats, version},
+    },
+    mining_control::MiningControl,
+    stats_store::StatsStore,
+};
+
 /// An HTTP server that provides stats and other useful information.
 pub struct HttpServer {
     shutdown_signal: ShutdownSignal,
     config: config::Config,
     stats_store: Arc<StatsStore>,
+    mining_control: Arc<MiningControl>,
 }
 
 #[derive(Error, Debug)]
@@ -24,25 +35,47 @@ pub enum Error {
 #[derive(Clone)]
 pub struct AppState {
     pub stats_store: Arc<StatsStore>,
+    pub mining_control: Arc<MiningControl>,
 }
 
 impl HttpServer {
-    pub fn new(shutdown_signal: ShutdownSignal, config: config::Config, stats_store: Arc<StatsStore>) -> Self {
+    pub fn new(
+        shutdown_signal: ShutdownSignal,
+        config: config::Config,
+        stats_store: Arc<StatsStore>,
+        mining_control: Arc<MiningControl>,
+    ) -> Self {
         Self {
             shutdown_signal,
             config,
             stats_store,
+            mining_control,
         }
     }
 
     pub fn routes(&self) -> Router {
-        Router::new()
+        let mut router = Router::new()
             .route("/health", get(health::handle_health))
             .route("/version", get(version::handle_version))
             .route("/stats", get(stats::handle_get_stats))
-            .with_state(AppState {
-                stats_store: self.stats_store.clone(),
-            })
+            .route("/metrics", get(metrics::handle_metrics))
+            .route("/blocks", get(blocks::handle_get_blocks));
+        if self.config.control_enabled {
+            router = router.merge(
+                Router::new()
+                    .route("/control/pause", post(control::handle_pause))
+                    .route("/control/resume", post(control::handle_resume))
+                    .route("/control/intensity", post(control::handle_intensity))
+                    .route_layer(middleware::from_fn_with_state(
+                        self.config.control_token.clone(),
+                        control::authorize,
+                    )),
+            );
+        }
+        router.with_state(AppState {
+            stats_store: self.stats_store.clone(),
+            mining_control: self.mining_control.clone(),
+        })
     }
 
     /// Starts the http server on the port passed in ['HttpServer::new']
@@ -52,7 +85,7 @@ impl HttpServer {
             .await
             .map_err(Error::IO)?;
         println!("Starting HTTP server at http://127.0.0.1:{}", self.config.port);
-        axum::serve(listener, router)
+        axum::serve(listener, router.into_make_service_with_connect_info::<SocketAddr>())
             .with_graceful_shutdown(self.shutdown_signal.clone())
             .await
             .map_err(Error::IO)?;
@@ -1,9 +1,18 @@
 use std::str::FromStr;
 use std::{cmp, fs};
-use std::{convert::TryInto, env::current_dir, path::PathBuf, sync::Arc, thread, time::Instant};
+use std::{
+    convert::TryInto,
+    env::current_dir,
+    path::PathBuf,
+    process,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
 
 use anyhow::{anyhow, Context as AnyContext, Error};
-use clap::Parser;
+use chrono::Local;
+use clap::{Parser, Subcommand};
 #[cfg(feature = "nvidia")]
 use cust::{
     memory::{AsyncCopyDestination, DeviceCopy},
@@ -19,6 +28,7 @@ use tari_common_types::{tari_address::TariAddress, types::FixedHash};
 use tari_core::{
     blocks::BlockHeader,
     consensus::ConsensusManager,
+    proof_of_work::sha3x_difficulty,
     transactions::{
         key_manager::create_memory_db_key_manager, tari_amount::MicroMinotari, transaction_components::RangeProofType,
     },
@@ -26,42 +36,77 @@ use tari_core::{
 use tari_shutdown::Shutdown;
 use tokio::{runtime::Runtime, sync::RwLock};
 
-#[cfg(feature = "nvidia")]
-use crate::cuda_engine::CudaEngine;
+use crate::autotune::{autotune_device, TuningCache};
+use crate::block_history::{NewBlockSubmission, SubmissionResult};
+use crate::device_info::{DeviceInfo, LaunchConfiguration};
+use crate::duty_cycle::DutyCycle;
+use crate::engine_type::EngineType;
 use crate::http::config::Config;
 use crate::http::server::HttpServer;
+use crate::mining_control::MiningControl;
+use crate::mining_policy::MiningPolicy;
+use crate::multi_engine::MultiEngine;
 use crate::node_client::ClientType;
-#[cfg(feature = "opencl3")]
-use crate::opencl_engine::OpenClEngine;
+use crate::nonce_allocator::NonceAllocator;
 use crate::stats_store::StatsStore;
+use crate::supervisor::{DeviceState, Supervisor};
+use crate::thermal::ThermalGovernor;
+use crate::watchdog::Watchdog;
+use crate::worker_context::WorkerContext;
 use crate::{
     config_file::ConfigFile, engine_impl::EngineImpl, function_impl::FunctionImpl, gpu_engine::GpuEngine,
-    node_client::NodeClient, tari_coinbase::generate_coinbase,
+    mine_result::MineResult, node_client::NodeClient, tari_coinbase::generate_coinbase,
 };
 use log::{error, info, warn};
 
+mod autotune;
+mod block_history;
 mod config_file;
 mod context_impl;
+#[cfg(feature = "cpu")]
+mod cpu_engine;
 #[cfg(feature = "nvidia")]
 mod cuda_engine;
+mod device_info;
+mod duty_cycle;
+mod engine_error;
 mod engine_impl;
+mod engine_type;
 mod function_impl;
 mod gpu_engine;
 mod http;
+mod mine_result;
+mod mining_control;
+mod mining_policy;
+mod multi_engine;
 mod node_client;
+mod nonce_allocator;
 #[cfg(feature = "opencl3")]
 mod opencl_engine;
 mod p2pool_client;
 mod stats_store;
+mod supervisor;
 mod tari_coinbase;
+mod thermal;
+mod watchdog;
+mod worker_context;
+
+#[cfg(not(any(feature = "nvidia", feature = "opencl3", feature = "cpu")))]
+compile_error!("At least one engine feature must be enabled: nvidia, opencl3 or cpu");
 
 const LOG_TARGET: &str = "tari::universe::gpu_miner"; //TODO set log target
+/// How often a thread that is blocked waiting, e.g. while paused or backing off, checks for shutdown
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// Recoverable engine errors tolerated in a row before the mining thread gives up on its device
+const MAX_CONSECUTIVE_ENGINE_ERRORS: u32 = 5;
+/// How often each mining thread prints its rolling hashrates
+const HASHRATE_PRINT_INTERVAL: Duration = Duration::from_secs(10);
 
 #[tokio::main]
 async fn main() {
     match main_inner().await {
         Ok(()) => {
-            info!(target: LOG_TARGET, "Starting gpu_miner");
+            info!(target: LOG_TARGET, "Gpu_miner stopped");
         },
         Err(err) => {
             error!(target: LOG_TARGET, "Gpu_miner error: {}", err);
@@ -72,6 +117,9 @@ async fn main() {
 
 #[derive(Parser)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Config file path
     #[arg(short, long, value_name = "FILE")]
     config: Option<PathBuf>,
@@ -102,9 +150,61 @@ struct Cli {
     #[arg(long)]
     http_server_port: Option<u16>,
 
-    /// GPU percentage in values 1-1000, where 500 = 50%
+    /// GPU percentage in values 1-1000, where 500 = busy 50% of the wall-clock time
     #[arg(long, alias = "gpu-usage")]
     gpu_percentage: Option<u16>,
+
+    /// (Optional) Number of worker threads used by the CPU engine
+    #[arg(long)]
+    cpu_threads: Option<u32>,
+
+    /// (Optional) Mining engine. `auto` uses the first compiled backend that reports devices
+    #[arg(long, value_enum)]
+    engine: Option<EngineType>,
+
+    /// (Optional) Devices to mine on, by index or device name substring, e.g. `0,1` or `RTX`
+    #[arg(long, value_delimiter = ',')]
+    devices: Option<Vec<String>>,
+
+    /// (Optional) Devices to leave idle, by index or device name substring
+    #[arg(long, value_delimiter = ',')]
+    excluded_devices: Option<Vec<String>>,
+
+    /// (Optional) Rig id that keeps this rig's nonces apart from other rigs. Random if not set
+    #[arg(long)]
+    rig_id: Option<u16>,
+
+    /// (Optional) OpenCL kernel source file to build instead of the built-in one
+    #[arg(long, value_name = "FILE")]
+    opencl_kernel: Option<PathBuf>,
+
+    /// (Optional) Extra options passed to the OpenCL compiler, e.g. `-cl-fast-relaxed-math`
+    #[arg(long, allow_hyphen_values = true)]
+    opencl_build_options: Option<String>,
+
+    /// (Optional) Preprocessor defines passed to the OpenCL compiler, e.g. `UNROLL=4,USE_BITSELECT`
+    #[arg(long, value_delimiter = ',')]
+    opencl_defines: Option<Vec<String>>,
+
+    /// (Optional) Name of the OpenCL kernel entry point
+    #[arg(long)]
+    opencl_kernel_name: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List every device each compiled backend can see
+    ListDevices {
+        /// Print the inventory as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Sweep launch parameters for every selected device and store the fastest ones for later runs
+    Autotune {
+        /// Seconds to measure each candidate configuration
+        #[arg(long, default_value_t = 3)]
+        duration_secs: u64,
+    },
 }
 
 async fn main_inner() -> Result<(), anyhow::Error> {
@@ -122,16 +222,22 @@ async fn main_inner() -> Result<(), anyhow::Error> {
             config
         },
         Err(err) => {
-            error!(target: LOG_TARGET, "Error loading config file: {}. Creating new one", err);
-            let default = ConfigFile::default();
             let path = cli.config.unwrap_or_else(|| {
                 let mut path = current_dir().expect("no current directory");
                 path.push("config.json");
                 path
             });
+            // An existing config that fails to parse or validate is reported rather than replaced
+            if path.exists() {
+                error!(target: LOG_TARGET, "Error loading config file {}: {}", path.display(), err);
+                return Err(err.context(format!("Invalid config file {}", path.display())));
+            }
+            error!(target: LOG_TARGET, "Error loading config file: {}. Creating new one", err);
+            let mut default = ConfigFile::default();
             dbg!(&path);
             fs::create_dir_all(path.parent().expect("no parent"))?;
             default.save(&path).expect("Could not save default config");
+            default.resolve_paths(&path);
             default
         },
     };
@@ -154,60 +260,327 @@ async fn main_inner() -> Result<(), anyhow::Error> {
     if let Some(percentage) = cli.gpu_percentage {
         config.gpu_percentage = percentage;
     }
+    if let Some(threads) = cli.cpu_threads {
+        config.cpu_threads = Some(threads);
+    }
+    if let Some(engine) = cli.engine {
+        config.engine = engine;
+    }
+    if let Some(devices) = cli.devices {
+        config.devices = devices;
+    }
+    if let Some(excluded_devices) = cli.excluded_devices {
+        config.excluded_devices = excluded_devices;
+    }
+    if let Some(rig_id) = cli.rig_id {
+        config.rig_id = Some(rig_id);
+    }
+    if let Some(kernel_path) = cli.opencl_kernel {
+        config.opencl_kernel_path = Some(kernel_path);
+    }
+    if let Some(build_options) = cli.opencl_build_options {
+        config.opencl_build_options = build_options;
+    }
+    if let Some(defines) = cli.opencl_defines {
+        config.opencl_defines = defines;
+    }
+    if let Some(kernel_name) = cli.opencl_kernel_name {
+        config.opencl_kernel_name = kernel_name;
+    }
+
+    if let Some(Command::ListDevices { json }) = cli.command {
+        return list_devices(&config, json);
+    }
+    let autotune_duration = match cli.command {
+        Some(Command::Autotune { duration_secs }) => Some(Duration::from_secs(duration_secs)),
+        _ => None,
+    };
 
     let submit = true;
 
-    #[cfg(feature = "nvidia")]
-    let mut gpu_engine = GpuEngine::new(CudaEngine::new());
+    let engine = MultiEngine::create(config.engine, &config)?;
+    println!("Using {} engine", engine.engine_type());
+    info!(target: LOG_TARGET, "Using {} engine", engine.engine_type());
+    let mut gpu_engine = GpuEngine::new(engine);
 
-    #[cfg(feature = "opencl3")]
-    let mut gpu_engine = GpuEngine::new(OpenClEngine::new());
+    gpu_engine.init()?;
+    let mut selected_devices = vec![];
+    for device in gpu_engine.list_devices()? {
+        if config.is_device_selected(device.index, &device.device_name) {
+            info!(target: LOG_TARGET, "Device: {}", device);
+            selected_devices.push(device);
+        } else {
+            println!("Skipping device {}: {}", device.index, device.device_name);
+            info!(target: LOG_TARGET, "Skipping device: {}", device);
+        }
+    }
+    if selected_devices.is_empty() {
+        return Err(anyhow!("No devices selected"));
+    }
 
-    gpu_engine.init().unwrap();
+    if let Some(duration) = autotune_duration {
+        return autotune(&gpu_engine, &selected_devices, &config, duration);
+    }
 
-    // http server
     let mut shutdown = Shutdown::new();
+    let shutdown_signal = shutdown.to_signal();
+    let shutdown_grace_period = Duration::from_secs(config.shutdown_grace_period_secs);
+    tokio::spawn(async move {
+        wait_for_os_signal().await;
+        println!("Shutting down, finishing in-flight batches");
+        info!(target: LOG_TARGET, "Shutdown requested, finishing in-flight batches");
+        shutdown.trigger();
+        // A device stuck in the driver never returns to its mining loop, so don't wait for it forever
+        tokio::select! {
+            _ = wait_for_os_signal() => {
+                println!("Second shutdown signal, exiting immediately");
+                warn!(target: LOG_TARGET, "Second shutdown signal, exiting immediately");
+            },
+            _ = tokio::time::sleep(shutdown_grace_period) => {
+                println!("Devices did not stop within {:?}, exiting", shutdown_grace_period);
+                error!(target: LOG_TARGET, "Devices did not stop within {:?}, exiting", shutdown_grace_period);
+            },
+        }
+        process::exit(1);
+    });
+
+    // http server
     let stats_store = Arc::new(StatsStore::new());
+    let mining_control = Arc::new(MiningControl::new());
+    let mining_policy = Arc::new(MiningPolicy::new(config.policy.clone())?);
+    let watchdog = Arc::new(Watchdog::new(
+        config.watchdog_timeout_multiplier,
+        Duration::from_secs(config.watchdog_min_timeout_secs),
+    ));
+    let watchdog_stats_store = stats_store.clone();
+    let watchdog_shutdown_signal = shutdown_signal.clone();
+    let watchdog_clone = watchdog.clone();
+    thread::spawn(move || watchdog_clone.run(watchdog_stats_store, watchdog_shutdown_signal));
+    let mut http_server_handle = None;
     if config.http_server_enabled {
-        let http_server_config = Config::new(config.http_server_port);
+        let http_server_config = Config::new(
+            config.http_server_port,
+            config.http_control_enabled,
+            config.http_control_token.clone(),
+        );
         info!(target: LOG_TARGET, "HTTP server runs on port: {}", &http_server_config.port);
-        let http_server = HttpServer::new(shutdown.to_signal(), http_server_config, stats_store.clone());
-        tokio::spawn(async move {
+        let http_server = HttpServer::new(
+            shutdown_signal.clone(),
+            http_server_config,
+            stats_store.clone(),
+            mining_control.clone(),
+        );
+        http_server_handle = Some(tokio::spawn(async move {
             if let Err(error) = http_server.start().await {
                 println!("Failed to start HTTP server: {error:?}");
                 error!(target: LOG_TARGET, "Failed to start HTTP server: {:?}", error);
             }
-        });
+        }));
     }
 
-    let num_devices = gpu_engine.num_devices()?;
+    // Rigs sharing a config must not share a rig id, otherwise they search the same nonces
+    let rig_id = config.rig_id.unwrap_or_else(rand::random);
+    println!("Rig id: {}", rig_id);
+    info!(target: LOG_TARGET, "Rig id: {}", rig_id);
+    let worker_context = WorkerContext {
+        stats_store: stats_store.clone(),
+        mining_control,
+        mining_policy,
+        watchdog,
+        shutdown_signal,
+    };
+    // Created up front, so a device the nonce layout can't hold fails the start before any device mines. Each one
+    // outlives restarts of its worker, so a restarted device doesn't search the nonces it already covered
+    let nonce_allocators = selected_devices
+        .iter()
+        .map(|device| Ok(Arc::new(Mutex::new(NonceAllocator::new(rig_id, device.index)?))))
+        .collect::<Result<Vec<_>, anyhow::Error>>()?;
     let mut threads = vec![];
-    for i in 0..num_devices {
+    for (device, nonce_allocator) in selected_devices.into_iter().zip(nonce_allocators) {
         let c = config.clone();
         let gpu = gpu_engine.clone();
-        let curr_stats_store = stats_store.clone();
+        let curr_worker_context = worker_context.clone();
         threads.push(thread::spawn(move || {
-            run_thread(gpu, num_devices as u64, i as u32, c, benchmark, curr_stats_store)
+            let supervisor = Supervisor::new(
+                device.index,
+                c.max_device_failures,
+                Duration::from_secs(c.device_restart_backoff_secs),
+                curr_worker_context.stats_store.clone(),
+                curr_worker_context.watchdog.clone(),
+                curr_worker_context.shutdown_signal.clone(),
+            );
+            supervisor.run(move |watchdog_generation| {
+                run_thread(
+                    gpu.clone(),
+                    &nonce_allocator,
+                    device.clone(),
+                    c.clone(),
+                    benchmark,
+                    &curr_worker_context,
+                    watchdog_generation,
+                )
+            })
         }));
     }
 
-    for t in threads {
-        t.join().unwrap()?;
+    // Joined off the async runtime, which must keep serving the signal task and the http server meanwhile
+    let device_states = tokio::task::spawn_blocking(move || {
+        threads
+            .into_iter()
+            .map(|t| t.join().unwrap_or(DeviceState::Disabled))
+            .collect::<Vec<_>>()
+    })
+    .await?;
+
+    if device_states.iter().all(|state| *state == DeviceState::Disabled) {
+        return Err(anyhow!("All devices were disabled after repeated failures"));
+    }
+    if let Some(http_server_handle) = http_server_handle {
+        http_server_handle.await?;
+    }
+    println!(
+        "Stopped. Accepted blocks: {}, rejected blocks: {}",
+        stats_store.accepted_blocks(),
+        stats_store.rejected_blocks()
+    );
+    info!(target: LOG_TARGET,
+        "Stopped. Accepted blocks: {}, rejected blocks: {}, invalid solutions: {:?}",
+        stats_store.accepted_blocks(),
+        stats_store.rejected_blocks(),
+        stats_store.invalid_solutions()
+    );
+
+    Ok(())
+}
+
+/// Resolves on Ctrl-C, or on SIGTERM on unix so a service manager can stop the miner
+async fn wait_for_os_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {},
+                    _ = terminate.recv() => {},
+                }
+            },
+            Err(error) => {
+                warn!(target: LOG_TARGET, "Could not listen for SIGTERM: {:?}", error);
+                let _ = tokio::signal::ctrl_c().await;
+            },
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+fn list_devices(config: &ConfigFile, json: bool) -> Result<(), anyhow::Error> {
+    let engine_types = match config.engine {
+        EngineType::Auto => MultiEngine::compiled_engines(),
+        engine_type => vec![engine_type],
+    };
+    let mut devices = vec![];
+    for engine_type in engine_types {
+        let mut gpu_engine = GpuEngine::new(MultiEngine::create(engine_type, config)?);
+        if let Err(error) = gpu_engine.init() {
+            warn!(target: LOG_TARGET, "Could not init {} engine: {:?}", engine_type, error);
+            continue;
+        }
+        let engine_devices = match gpu_engine.list_devices() {
+            Ok(engine_devices) => engine_devices,
+            Err(error) => {
+                warn!(target: LOG_TARGET, "Could not list {} devices: {:?}", engine_type, error);
+                continue;
+            },
+        };
+        for mut device in engine_devices {
+            device.launch_configuration = match suggested_launch_configuration(&gpu_engine, device.index) {
+                Ok(launch_configuration) => Some(launch_configuration),
+                Err(error) => {
+                    warn!(target: LOG_TARGET,
+                        "Could not get launch configuration of {} device {}: {:?}", engine_type, device.index, error
+                    );
+                    None
+                },
+            };
+            devices.push(device);
+        }
     }
 
-    shutdown.trigger();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&devices)?);
+    } else {
+        for device in &devices {
+            println!("{}", device);
+        }
+    }
+    Ok(())
+}
 
+fn autotune<T: EngineImpl>(
+    gpu_engine: &GpuEngine<T>,
+    devices: &[DeviceInfo],
+    config: &ConfigFile,
+    duration_per_candidate: Duration,
+) -> Result<(), anyhow::Error> {
+    let cache_path = config.tuning_cache_path();
+    let mut cache = TuningCache::load(&cache_path)?;
+    for device in devices {
+        println!("Autotuning device {}: {}", device.index, device.device_name);
+        info!(target: LOG_TARGET, "Autotuning device {}: {}", device.index, device.device_name);
+        match autotune_device(gpu_engine, device, duration_per_candidate) {
+            Ok(tuned) => {
+                println!(
+                    "Device {}: best grid: {} block: {} iterations: {} hashes/sec: {}",
+                    device.index,
+                    tuned.grid_size,
+                    tuned.block_size,
+                    tuned.num_iterations,
+                    tuned.hashes_per_second.to_formatted_string(&Locale::en)
+                );
+                info!(target: LOG_TARGET, "Device {}: tuned configuration: {:?}", device.index, tuned);
+                cache.insert(device, tuned);
+                cache.save(&cache_path)?;
+            },
+            Err(error) => {
+                println!("Could not autotune device {}: {:?}", device.index, error);
+                error!(target: LOG_TARGET, "Could not autotune device {}: {:?}", device.index, error);
+            },
+        }
+    }
     Ok(())
 }
 
+fn suggested_launch_configuration<T: EngineImpl>(
+    gpu_engine: &GpuEngine<T>,
+    device_index: u32,
+) -> Result<LaunchConfiguration, anyhow::Error> {
+    let context = gpu_engine.create_context(device_index)?;
+    let function = gpu_engine.get_main_function(&context)?;
+    let (grid_size, block_size) = function.suggested_launch_configuration()?;
+    Ok(LaunchConfiguration { grid_size, block_size })
+}
+
 fn run_thread<T: EngineImpl>(
     gpu_engine: GpuEngine<T>,
-    num_threads: u64,
-    thread_index: u32,
+    nonce_allocator: &Mutex<NonceAllocator>,
+    device: DeviceInfo,
     config: ConfigFile,
     benchmark: bool,
-    stats_store: Arc<StatsStore>,
+    worker_context: &WorkerContext,
+    watchdog_generation: u64,
 ) -> Result<(), anyhow::Error> {
+    let WorkerContext {
+        stats_store,
+        mining_control,
+        mining_policy,
+        watchdog,
+        shutdown_signal,
+    } = worker_context;
+    let thread_index = device.index;
     let tari_node_url = config.tari_node_url.clone();
     let runtime = Runtime::new()?;
     let client_type = if benchmark {
@@ -217,22 +590,76 @@ fn run_thread<T: EngineImpl>(
     } else {
         ClientType::BaseNode
     };
-    let node_client =
-        Arc::new(RwLock::new(runtime.block_on(async move {
-            node_client::create_client(client_type, &tari_node_url).await
-        })?));
+    let client_type_name = client_type.name();
+    // Connecting retries until the node is up, so it must give way to a shutdown
+    let connect_shutdown_signal = shutdown_signal.clone();
+    let Some(node_client) = runtime.block_on(async move {
+        tokio::select! {
+            client = node_client::create_client(client_type, &tari_node_url) => Some(client),
+            _ = connect_shutdown_signal => None,
+        }
+    }) else {
+        return Ok(());
+    };
+    let node_client = Arc::new(RwLock::new(node_client?));
     let mut rounds = 0;
+    let mut consecutive_engine_errors = 0;
 
     let context = gpu_engine.create_context(thread_index)?;
 
     let gpu_function = gpu_engine.get_main_function(&context)?;
 
-    let (mut grid_size, block_size) = gpu_function
+    let (suggested_grid_size, suggested_block_size) = gpu_function
         .suggested_launch_configuration()
         .context("get suggest config")?;
+    let tuned = match TuningCache::load(&config.tuning_cache_path()) {
+        Ok(cache) => cache.get(&device),
+        Err(error) => {
+            warn!(target: LOG_TARGET, "Could not load tuning cache: {:?}", error);
+            None
+        },
+    };
+    let device_override = config.device_override(thread_index, &device.device_name);
+    let grid_size = device_override
+        .grid_size
+        .or(tuned.map(|t| t.grid_size))
+        .unwrap_or(suggested_grid_size);
+    let block_size = device_override
+        .block_size
+        .or(tuned.map(|t| t.block_size))
+        .unwrap_or(suggested_block_size);
+    let num_iterations = device_override
+        .num_iterations
+        .or(tuned.map(|t| t.num_iterations))
+        .unwrap_or(config.num_iterations);
+    // The kernels step through nonces with 32-bit arithmetic, so a larger batch would wrap and search nonces twice
+    if grid_size
+        .checked_mul(block_size)
+        .and_then(|n| n.checked_mul(num_iterations))
+        .is_none()
+    {
+        return Err(anyhow!(
+            "Device {}: grid size {} * block size {} * num iterations {} does not fit in 32 bits",
+            thread_index,
+            grid_size,
+            block_size,
+            num_iterations
+        ));
+    }
+    let mut duty_cycle = DutyCycle::new(device_override.gpu_percentage.unwrap_or(config.gpu_percentage));
+    let gpu_percentage = duty_cycle.percentage();
+    let configured_gpu_percentage = gpu_percentage;
     // let (grid_size, block_size) = (23, 50);
-    grid_size =
-        (grid_size as f64 / 1000f64 * cmp::max(cmp::min(100, config.gpu_percentage as usize), 1) as f64).round() as u32;
+    println!(
+        "Device {} ({}): grid size: {}, block size: {}, num iterations: {}, gpu percentage: {}",
+        thread_index, device.device_name, grid_size, block_size, num_iterations, gpu_percentage
+    );
+    info!(target: LOG_TARGET,
+        "Device {} ({}): grid size: {}, block size: {}, num iterations: {}, gpu percentage: {}",
+        thread_index, device.device_name, grid_size, block_size, num_iterations, gpu_percentage
+    );
+
+    let mut thermal_governor = ThermalGovernor::new(&config.thermal, thread_index, &device.device_name);
 
     let output = vec![0u64; 5];
     // let mut output_buf = output.as_slice().as_dbuf()?;
@@ -240,7 +667,23 @@ fn run_thread<T: EngineImpl>(
     let mut data = vec![0u64; 6];
     // let mut data_buf = data.as_slice().as_dbuf()?;
 
+    let mut batch_handler = BatchHandler {
+        thread_index,
+        runtime: &runtime,
+        node_client: &node_client,
+        stats_store,
+        watchdog,
+        watchdog_generation,
+        client_type_name,
+        p2pool_enabled: config.p2pool_enabled,
+        invalid_solutions: 0,
+        job_submitted: false,
+    };
+    stats_store.start_device(thread_index);
     loop {
+        if shutdown_signal.is_triggered() {
+            break;
+        }
         rounds += 1;
         if rounds > 101 {
             rounds = 0;
@@ -251,9 +694,20 @@ fn run_thread<T: EngineImpl>(
         let mut block: Block;
         let mut header: BlockHeader;
         let mut mining_hash: FixedHash;
-        match runtime.block_on(async move { get_template(clone_config, clone_node_client, rounds, benchmark).await }) {
+        let template_requested = Instant::now();
+        let template_shutdown_signal = shutdown_signal.clone();
+        let Some(template) = runtime.block_on(async move {
+            tokio::select! {
+                template = get_template(clone_config, clone_node_client, rounds, benchmark) => Some(template),
+                _ = template_shutdown_signal => None,
+            }
+        }) else {
+            break;
+        };
+        match template {
             Ok((res_target_difficulty, res_block, res_header, res_mining_hash)) => {
                 info!(target: LOG_TARGET, "Getting next block...");
+                stats_store.record_template(res_header.height, res_target_difficulty, template_requested.elapsed());
                 target_difficulty = res_target_difficulty;
                 block = res_block;
                 header = res_header;
@@ -266,32 +720,92 @@ fn run_thread<T: EngineImpl>(
             },
         }
 
-        let hash64 = copy_u8_to_u64(mining_hash.to_vec());
-        data[0] = 0;
-        data[1] = hash64[0];
-        data[2] = hash64[1];
-        data[3] = hash64[2];
-        data[4] = hash64[3];
-        data[5] = u64::from_le_bytes([1, 0x06, 0, 0, 0, 0, 0, 0]);
+        write_job_data(&mut data, &mining_hash);
+        batch_handler.start_job();
         // data_buf.copy_from(&data).expect("Could not copy data to buffer");
         // output_buf.copy_from(&output).expect("Could not copy output to buffer");
 
-        let mut nonce_start = (u64::MAX / num_threads) * thread_index as u64;
+        let mut epoch = nonce_allocator.lock().unwrap().start_job(mining_hash);
+        info!(target: LOG_TARGET,
+            "Device {} mining epoch {}, {} nonces already covered",
+            thread_index,
+            epoch,
+            nonce_allocator.lock().unwrap().covered()
+        );
+        let chunk_size = u64::from(grid_size) * u64::from(block_size) * u64::from(num_iterations);
+        let mut total_hashes = 0u64;
         let mut last_hash_rate = 0;
         let elapsed = Instant::now();
         let mut max_diff = 0;
         let mut last_printed = Instant::now();
+        // Time spent fetching the template, or paused or idle before it, must not count towards the duty cycle,
+        // otherwise the device would run flat out until the window rolls over
+        duty_cycle.restart_window();
+        // Set when mining stops before the template is due for a refresh, and handled once the batches still in flight
+        // have been flushed
+        let mut interruption = None;
         loop {
-            if elapsed.elapsed().as_secs() > config.template_refresh_secs {
+            if shutdown_signal.is_triggered() || elapsed.elapsed().as_secs() > config.template_refresh_secs {
+                break;
+            }
+            if mining_control.is_paused() {
+                interruption = Some(Interruption::Paused);
+                break;
+            }
+            let policy_decision = mining_policy.decision();
+            stats_store.update_policy(policy_decision.clone());
+            if !policy_decision.active {
+                interruption = Some(Interruption::Idle(policy_decision.reasons));
                 break;
             }
-            let num_iterations = 16;
-            let (nonce, hashes, diff) = gpu_engine.mine(
+            let wanted_gpu_percentage = mining_control.gpu_percentage().unwrap_or(configured_gpu_percentage);
+            if wanted_gpu_percentage != duty_cycle.percentage() {
+                duty_cycle.set_percentage(wanted_gpu_percentage);
+                info!(target: LOG_TARGET,
+                    "Device {} gpu percentage set to {}", thread_index, duty_cycle.percentage()
+                );
+            }
+            // Locked only briefly, so a worker abandoned in a hung batch never holds it
+            let next_chunk = nonce_allocator.lock().unwrap().next_chunk(chunk_size);
+            let Some(chunk) = next_chunk else {
+                if nonce_allocator.lock().unwrap().covered() == 0 {
+                    return Err(anyhow!(
+                        "Device {}: a batch of {} nonces does not fit in the nonce space of a job",
+                        thread_index,
+                        chunk_size
+                    ));
+                }
+                // The batches in flight were mined for the header that is about to change
+                let flushed =
+                    batch_handler.flush(&gpu_engine, &context, &mut header, &block, target_difficulty, &data)?;
+                total_hashes += flushed.hashes;
+                if flushed.submitted {
+                    break;
+                }
+                // Moving the timestamp on gives a new mining hash with a fresh nonce space, even when the node keeps
+                // handing out the same template
+                header.timestamp = header.timestamp.increase(1);
+                mining_hash = header.mining_hash();
+                write_job_data(&mut data, &mining_hash);
+                batch_handler.start_job();
+                let exhausted_epoch = epoch;
+                epoch = nonce_allocator.lock().unwrap().start_job(mining_hash);
+                println!("Device {} searched the whole nonce space of epoch {}", thread_index, exhausted_epoch);
+                warn!(target: LOG_TARGET,
+                    "Device {} searched the whole nonce space of epoch {}, moved the timestamp on to epoch {}",
+                    thread_index,
+                    exhausted_epoch,
+                    epoch
+                );
+                continue;
+            };
+            watchdog.batch_started(thread_index, watchdog_generation);
+            let mine_result = gpu_engine.mine(
                 &gpu_function,
                 &context,
                 &data,
                 (u64::MAX / (target_difficulty)).to_le(),
-                nonce_start,
+                chunk.start,
                 num_iterations,
                 block_size,
                 grid_size, /* &context,
@@ -302,58 +816,272 @@ fn run_thread<T: EngineImpl>(
                             * grid_size,
                             * data_buf.as_device_ptr(),
                             * &output_buf, */
-            )?;
-            if let Some(ref n) = nonce {
-                header.nonce = *n;
+            );
+            if watchdog.batch_completed(thread_index, watchdog_generation) {
+                return Err(anyhow!("Device {} hung in a batch, dropping its context", thread_index));
             }
-            if diff > max_diff {
-                max_diff = diff;
+            let result = match mine_result {
+                Ok(result) => {
+                    consecutive_engine_errors = 0;
+                    result
+                },
+                Err(error) if error.is_recoverable() && consecutive_engine_errors < MAX_CONSECUTIVE_ENGINE_ERRORS => {
+                    consecutive_engine_errors += 1;
+                    stats_store.inc_errors(thread_index);
+                    println!("Device {} mining error, retrying: {}", thread_index, error);
+                    warn!(target: LOG_TARGET,
+                        "Device {} mining error ({} in a row), retrying: {:?}",
+                        thread_index, consecutive_engine_errors, error
+                    );
+                    continue;
+                },
+                Err(error) => {
+                    stats_store.inc_errors(thread_index);
+                    return Err(error.into());
+                },
+            };
+            if result.best_difficulty() > max_diff {
+                max_diff = result.best_difficulty();
             }
-            nonce_start = nonce_start + hashes as u64;
+            total_hashes += result.hashes;
+            stats_store.add_hashes(thread_index, result.hashes);
             if elapsed.elapsed().as_secs() > 1 {
-                if Instant::now() - last_printed > std::time::Duration::from_secs(2) {
+                let hash_rate = total_hashes / elapsed.elapsed().as_secs();
+                stats_store.update_hashes_per_second(thread_index, hash_rate);
+                if Instant::now() - last_printed > HASHRATE_PRINT_INTERVAL {
                     last_printed = Instant::now();
-                    let hash_rate = nonce_start / elapsed.elapsed().as_secs();
-                    stats_store.update_hashes_per_second(hash_rate);
+                    let hashrate = stats_store.device_hashrate(thread_index);
                     println!(
-                        "total {:} grid: {} max_diff: {}, target: {} hashes/sec: {}",
-                        nonce_start.to_formatted_string(&Locale::en),
+                        "total {:} grid: {} max_diff: {}, target: {} hashes/sec 10s: {} 60s: {} 15m: {} avg: {}",
+                        total_hashes.to_formatted_string(&Locale::en),
                         grid_size,
                         max_diff.to_formatted_string(&Locale::en),
                         target_difficulty.to_formatted_string(&Locale::en),
-                        hash_rate.to_formatted_string(&Locale::en)
+                        hashrate.last_10s.to_formatted_string(&Locale::en),
+                        hashrate.last_60s.to_formatted_string(&Locale::en),
+                        hashrate.last_15m.to_formatted_string(&Locale::en),
+                        hashrate.session_average.to_formatted_string(&Locale::en)
                     );
-                    info!(target: LOG_TARGET,                         
-                    "total {:} grid: {} max_diff: {}, target: {} hashes/sec: {}",
-                    nonce_start.to_formatted_string(&Locale::en),
+                    info!(target: LOG_TARGET,
+                    "total {:} grid: {} max_diff: {}, target: {} hashes/sec 10s: {} 60s: {} 15m: {} avg: {}",
+                    total_hashes.to_formatted_string(&Locale::en),
                     grid_size,
                     max_diff.to_formatted_string(&Locale::en),
                     target_difficulty.to_formatted_string(&Locale::en),
-                    hash_rate.to_formatted_string(&Locale::en));
+                    hashrate.last_10s.to_formatted_string(&Locale::en),
+                    hashrate.last_60s.to_formatted_string(&Locale::en),
+                    hashrate.last_15m.to_formatted_string(&Locale::en),
+                    hashrate.session_average.to_formatted_string(&Locale::en));
                 }
             }
-            if nonce.is_some() {
-                header.nonce = nonce.unwrap();
-
-                let mut mined_block = block.clone();
-                mined_block.header = Some(grpc_header::from(header));
-                let clone_client = node_client.clone();
-                match runtime.block_on(async { clone_client.write().await.submit_block(mined_block).await }) {
-                    Ok(_) => {
-                        stats_store.inc_accepted_blocks();
-                        println!("Block submitted");
-                        info!(target: LOG_TARGET, "Block submitted");
-                    },
-                    Err(e) => {
-                        stats_store.inc_rejected_blocks();
-                        println!("Error submitting block: {:?}", e);
-                        error!(target: LOG_TARGET, "Error submitting block: {:?}", e);
-                    },
+            if batch_handler.submit_solutions(&result, &mut header, &block, target_difficulty, &data) {
+                break;
+            }
+            if let Some(reading) = thermal_governor.poll() {
+                stats_store.update_thermal(thread_index, reading);
+            }
+            let thermal_pause = thermal_governor.pause(result.kernel_time);
+            if !cmp::max(duty_cycle.pause(result.kernel_time), thermal_pause).is_zero() {
+                // A pipelined engine keeps the next batch queued, so the device only idles once that is flushed too
+                let flushed =
+                    batch_handler.flush(&gpu_engine, &context, &mut header, &block, target_difficulty, &data)?;
+                total_hashes += flushed.hashes;
+                if flushed.submitted {
+                    break;
                 }
+                let pause = cmp::max(
+                    duty_cycle.pause(flushed.kernel_time),
+                    thermal_pause + thermal_governor.pause(flushed.kernel_time),
+                );
+                thread::sleep(pause);
+            }
+        }
+        // Whatever is still in flight was mined for this template
+        batch_handler.flush(&gpu_engine, &context, &mut header, &block, target_difficulty, &data)?;
+        // The template may have gone stale while paused or idle, so a new one is fetched either way
+        match interruption {
+            Some(Interruption::Paused) => {
+                stats_store.update_hashes_per_second(thread_index, 0);
+                println!("Device {} paused", thread_index);
+                info!(target: LOG_TARGET, "Device {} paused", thread_index);
+                mining_control.wait_while_paused(shutdown_signal);
+                println!("Device {} resumed", thread_index);
+                info!(target: LOG_TARGET, "Device {} resumed", thread_index);
+            },
+            Some(Interruption::Idle(reasons)) => {
+                stats_store.update_hashes_per_second(thread_index, 0);
+                info!(target: LOG_TARGET, "Device {} idle: {}", thread_index, reasons.join(", "));
+                mining_policy.wait_until_active(shutdown_signal);
+                info!(target: LOG_TARGET, "Device {} active again", thread_index);
+                stats_store.update_policy(mining_policy.decision());
+            },
+            None => {},
+        }
+    }
+    stats_store.update_hashes_per_second(thread_index, 0);
+    println!("Device {} stopped", thread_index);
+    info!(target: LOG_TARGET, "Device {} stopped", thread_index);
+    Ok(())
+}
+
+/// Why a mining thread stopped before its template was due for a refresh
+enum Interruption {
+    Paused,
+    Idle(Vec<String>),
+}
+
+/// Batches flushed from a pipelined engine
+struct FlushedBatches {
+    hashes: u64,
+    kernel_time: Duration,
+    submitted: bool,
+}
+
+/// Verifies the solutions of a device's finished batches and submits them
+struct BatchHandler<'a> {
+    thread_index: u32,
+    runtime: &'a Runtime,
+    node_client: &'a Arc<RwLock<node_client::Client>>,
+    stats_store: &'a StatsStore,
+    watchdog: &'a Watchdog,
+    watchdog_generation: u64,
+    client_type_name: &'static str,
+    p2pool_enabled: bool,
+    invalid_solutions: u64,
+    /// Whether a block was submitted for the current header
+    job_submitted: bool,
+}
+
+impl BatchHandler<'_> {
+    /// Called whenever the header changes
+    fn start_job(&mut self) {
+        self.job_submitted = false;
+    }
+
+    /// Checks the solutions of a finished batch against `header`, which must be the header the batch was mined for,
+    /// and submits the valid ones. Returns true if a block was submitted.
+    fn submit_solutions(
+        &mut self,
+        result: &MineResult,
+        header: &mut BlockHeader,
+        block: &Block,
+        target_difficulty: u64,
+        data: &[u64],
+    ) -> bool {
+        let mut solutions = result.solutions.clone();
+        solutions.sort_by_key(|solution| solution.hash);
+        let mut submitted = false;
+        for solution in solutions {
+            // A base node only takes one block per template, while every solution is a share for p2pool
+            if self.job_submitted && !self.p2pool_enabled {
                 break;
             }
-            // break;
+            let n = solution.nonce;
+            header.nonce = n;
+            let achieved_difficulty = match sha3x_difficulty(header) {
+                Ok(difficulty) => difficulty.as_u64(),
+                Err(error) => {
+                    error!(target: LOG_TARGET, "Could not calculate difficulty of nonce {}: {:?}", n, error);
+                    0
+                },
+            };
+            if achieved_difficulty < target_difficulty {
+                self.invalid_solutions += 1;
+                self.stats_store.inc_invalid_solutions(self.thread_index);
+                println!(
+                    "Device {} returned invalid nonce {} (difficulty {} < target {}), total invalid: {}",
+                    self.thread_index, n, achieved_difficulty, target_difficulty, self.invalid_solutions
+                );
+                error!(target: LOG_TARGET,
+                    "Device {} returned invalid nonce. Achieved difficulty: {}, target: {}, total invalid: {}, \
+                     kernel output: {:?}, data: {:?}",
+                    self.thread_index,
+                    achieved_difficulty,
+                    target_difficulty,
+                    self.invalid_solutions,
+                    result,
+                    data
+                );
+                continue;
+            }
+
+            self.stats_store.inc_solutions(self.thread_index);
+            let mut mined_block = block.clone();
+            mined_block.header = Some(grpc_header::from(header.clone()));
+            let clone_client = self.node_client.clone();
+            let submit_started = Instant::now();
+            let submit_result =
+                self.runtime.block_on(async { clone_client.write().await.submit_block(mined_block).await });
+            self.stats_store.record_submit_latency(submit_started.elapsed());
+            let (submission_result, response) = match submit_result {
+                Ok(response) => {
+                    println!("Block submitted");
+                    info!(target: LOG_TARGET, "Block submitted");
+                    (SubmissionResult::Accepted, response)
+                },
+                Err(e) => {
+                    // If the node already wants a later block, the chain moved on while this one was mined and it
+                    // lost the race
+                    let next_height =
+                        self.runtime.block_on(async { self.node_client.write().await.next_block_height().await });
+                    if matches!(next_height, Ok(next_height) if next_height > header.height) {
+                        println!("Stale block rejected: {:?}", e);
+                        warn!(target: LOG_TARGET, "Stale block at height {} rejected: {:?}", header.height, e);
+                        (SubmissionResult::Stale, format!("{:?}", e))
+                    } else {
+                        println!("Error submitting block: {:?}", e);
+                        error!(target: LOG_TARGET, "Error submitting block: {:?}", e);
+                        (SubmissionResult::Rejected, format!("{:?}", e))
+                    }
+                },
+            };
+            self.stats_store.record_submission(NewBlockSubmission {
+                time: Local::now(),
+                device_index: self.thread_index,
+                height: header.height,
+                block_hash: header.hash().to_string(),
+                nonce: n,
+                achieved_difficulty,
+                target_difficulty,
+                client_type: self.client_type_name.to_string(),
+                result: submission_result,
+                response,
+            });
+            submitted = true;
+            self.job_submitted = true;
         }
+        submitted
+    }
+
+    /// Waits for the batches the engine still has in flight and submits their solutions. Called before the header
+    /// changes or the device idles, so nothing those batches found or hashed is lost.
+    fn flush<T: EngineImpl>(
+        &mut self,
+        gpu_engine: &GpuEngine<T>,
+        context: &T::Context,
+        header: &mut BlockHeader,
+        block: &Block,
+        target_difficulty: u64,
+        data: &[u64],
+    ) -> Result<FlushedBatches, anyhow::Error> {
+        self.watchdog.batch_started(self.thread_index, self.watchdog_generation);
+        let results = gpu_engine.flush(context);
+        if self.watchdog.batch_completed(self.thread_index, self.watchdog_generation) {
+            return Err(anyhow!("Device {} hung in a batch, dropping its context", self.thread_index));
+        }
+        let results = results.inspect_err(|_| self.stats_store.inc_errors(self.thread_index))?;
+        let mut flushed = FlushedBatches {
+            hashes: 0,
+            kernel_time: Duration::ZERO,
+            submitted: false,
+        };
+        for result in results {
+            flushed.hashes += result.hashes;
+            flushed.kernel_time += result.kernel_time;
+            self.stats_store.add_hashes(self.thread_index, result.hashes);
+            flushed.submitted |= self.submit_solutions(&result, header, block, target_difficulty, data);
+        }
+        Ok(flushed)
     }
 }
 
@@ -365,12 +1093,9 @@ async fn get_template(
 ) -> Result<(u64, minotari_app_grpc::tari_rpc::Block, BlockHeader, FixedHash), anyhow::Error> {
     if benchmark {
         info!(target: LOG_TARGET, "Getting template with benchmark");
-        return Ok((
-            u64::MAX,
-            minotari_app_grpc::tari_rpc::Block::default(),
-            BlockHeader::new(0),
-            FixedHash::default(),
-        ));
+        let header = BlockHeader::new(0);
+        let mining_hash = header.mining_hash();
+        return Ok((u64::MAX, minotari_app_grpc::tari_rpc::Block::default(), header, mining_hash));
     }
     let address = if round % 99 == 0 {
         TariAddress::from_str(
@@ -447,6 +1172,17 @@ async fn get_template(
     Ok((target_difficulty, block, header, mining_hash))
 }
 
+/// Writes the job for `mining_hash` in the layout the kernels expect
+fn write_job_data(data: &mut [u64], mining_hash: &FixedHash) {
+    let hash64 = copy_u8_to_u64(mining_hash.to_vec());
+    data[0] = 0;
+    data[1] = hash64[0];
+    data[2] = hash64[1];
+    data[3] = hash64[2];
+    data[4] = hash64[3];
+    data[5] = u64::from_le_bytes([1, 0x06, 0, 0, 0, 0, 0, 0]);
+}
+
 fn copy_u8_to_u64(input: Vec<u8>) -> Vec<u64> {
     let mut output: Vec<u64> = Vec::with_capacity(input.len() / 8);
 
@@ -0,0 +1,57 @@
+use std::time::Duration;
+
+/// Maximum number of solutions a kernel can report per launch. Fixed at this size in keccak.ptx
+pub const MAX_SOLUTIONS: usize = 16;
+/// Number of words in the job data `run_thread` passes to the kernels
+pub const DATA_WORDS: usize = 6;
+/// Kernel output: solution count, lowest hash seen and a (nonce, hash) pair per solution, see
+/// [`MineResult::from_kernel_output`]
+pub const OUTPUT_WORDS: usize = 2 + 2 * MAX_SOLUTIONS;
+
+/// A nonce whose hash met the requested difficulty
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Solution {
+    pub nonce: u64,
+    /// First 8 bytes of the SHA3x hash, read big-endian
+    pub hash: u64,
+}
+
+/// Outcome of a single `EngineImpl::mine` call
+#[derive(Debug, Clone)]
+pub struct MineResult {
+    pub solutions: Vec<Solution>,
+    /// Number of hashes computed
+    pub hashes: u64,
+    /// Lowest hash seen, first 8 bytes read big-endian
+    pub best_hash: u64,
+    pub kernel_time: Duration,
+}
+
+impl MineResult {
+    /// Reads the kernel output ring: `[count, best_hash, nonce_0, hash_0, nonce_1, hash_1, ...]`
+    pub fn from_kernel_output(output: &[u64], hashes: u64, kernel_time: Duration) -> Self {
+        let count = output[0].min(MAX_SOLUTIONS as u64) as usize;
+        let solutions = output[2..2 + count * 2]
+            .chunks_exact(2)
+            .map(|pair| Solution {
+                nonce: pair[0],
+                hash: pair[1],
+            })
+            .collect();
+        Self {
+            solutions,
+            hashes,
+            best_hash: output[1],
+            kernel_time,
+        }
+    }
+
+    /// Difficulty of the best hash, compatible with the target difficulty of a block template
+    pub fn best_difficulty(&self) -> u64 {
+        if self.best_hash > 0 {
+            u64::MAX / self.best_hash
+        } else {
+            0
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use std::sync::{Condvar, Mutex};
+
+use serde::{Deserialize, Serialize};
+use tari_shutdown::ShutdownSignal;
+
+use crate::SHUTDOWN_POLL_INTERVAL;
+
+/// Runtime mining state that can be changed while the miner is running, e.g. over HTTP
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct ControlState {
+    pub paused: bool,
+    /// Overrides the configured `gpu_percentage` of every device when set
+    pub gpu_percentage: Option<u16>,
+}
+
+/// Control state shared between the HTTP server and the mining threads
+pub struct MiningControl {
+    state: Mutex<ControlState>,
+    changed: Condvar,
+}
+
+impl MiningControl {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(ControlState::default()),
+            changed: Condvar::new(),
+        }
+    }
+
+    pub fn state(&self) -> ControlState {
+        *self.state.lock().unwrap()
+    }
+
+    pub fn pause(&self) -> ControlState {
+        self.update(|state| state.paused = true)
+    }
+
+    pub fn resume(&self) -> ControlState {
+        self.update(|state| state.paused = false)
+    }
+
+    /// Sets the intensity of every device, or restores the configured one with `None`
+    pub fn set_gpu_percentage(&self, gpu_percentage: Option<u16>) -> ControlState {
+        self.update(|state| state.gpu_percentage = gpu_percentage)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state.lock().unwrap().paused
+    }
+
+    pub fn gpu_percentage(&self) -> Option<u16> {
+        self.state.lock().unwrap().gpu_percentage
+    }
+
+    /// Blocks the calling mining thread until mining is resumed or the miner shuts down
+    pub fn wait_while_paused(&self, shutdown_signal: &ShutdownSignal) {
+        let mut state = self.state.lock().unwrap();
+        while state.paused && !shutdown_signal.is_triggered() {
+            state = self
+                .changed
+                .wait_timeout_while(state, SHUTDOWN_POLL_INTERVAL, |state| state.paused)
+                .unwrap()
+                .0;
+        }
+    }
+
+    fn update<F: FnOnce(&mut ControlState)>(&self, f: F) -> ControlState {
+        let mut state = self.state.lock().unwrap();
+        f(&mut state);
+        self.changed.notify_all();
+        *state
+    }
+}
@@ -0,0 +1,331 @@
+use std::{
+    fs,
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use chrono::{Local, NaiveTime};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tari_shutdown::ShutdownSignal;
+
+use crate::{config_file::MiningPolicyConfig, LOG_TARGET, SHUTDOWN_POLL_INTERVAL};
+
+/// Whether mining is allowed right now, and if not, why
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PolicyDecision {
+    pub active: bool,
+    pub reasons: Vec<String>,
+}
+
+impl Default for PolicyDecision {
+    fn default() -> Self {
+        Self {
+            active: true,
+            reasons: vec![],
+        }
+    }
+}
+
+/// Daily window in local time. A window whose end is before its start runs over midnight
+#[derive(Debug, Clone, Copy)]
+struct TimeWindow {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl TimeWindow {
+    fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+struct PolicyState {
+    last_check: Option<Instant>,
+    decision: PolicyDecision,
+}
+
+/// Decides when mining is active from the policies in [`MiningPolicyConfig`]. Decisions are cached for
+/// `check_interval_secs`, so every mining thread can consult it between `mine` calls.
+pub struct MiningPolicy {
+    config: MiningPolicyConfig,
+    time_windows: Vec<TimeWindow>,
+    state: Mutex<PolicyState>,
+}
+
+impl MiningPolicy {
+    pub fn new(config: MiningPolicyConfig) -> Result<Self, anyhow::Error> {
+        let time_windows = config
+            .time_windows
+            .iter()
+            .map(|window| {
+                Ok(TimeWindow {
+                    start: parse_time(&window.start)?,
+                    end: parse_time(&window.end)?,
+                })
+            })
+            .collect::<Result<Vec<_>, anyhow::Error>>()?;
+        Ok(Self {
+            config,
+            time_windows,
+            state: Mutex::new(PolicyState {
+                last_check: None,
+                decision: PolicyDecision::default(),
+            }),
+        })
+    }
+
+    /// Returns the current decision, re-evaluating the policies if the cached one is too old
+    pub fn decision(&self) -> PolicyDecision {
+        let mut state = self.state.lock().unwrap();
+        let check_interval = Duration::from_secs(self.config.check_interval_secs);
+        if state
+            .last_check
+            .is_some_and(|last_check| last_check.elapsed() < check_interval)
+        {
+            return state.decision.clone();
+        }
+        let decision = self.evaluate();
+        if state.last_check.is_none() || decision != state.decision {
+            if decision.active {
+                println!("Mining policy: active");
+                info!(target: LOG_TARGET, "Mining policy: active");
+            } else {
+                println!("Mining policy: inactive ({})", decision.reasons.join(", "));
+                info!(target: LOG_TARGET, "Mining policy: inactive ({})", decision.reasons.join(", "));
+            }
+        }
+        state.last_check = Some(Instant::now());
+        state.decision = decision.clone();
+        decision
+    }
+
+    /// Blocks the calling mining thread until the policies allow mining again or the miner shuts down
+    pub fn wait_until_active(&self, shutdown_signal: &ShutdownSignal) {
+        while !shutdown_signal.is_triggered() && !self.decision().active {
+            thread::sleep(SHUTDOWN_POLL_INTERVAL);
+        }
+    }
+
+    fn evaluate(&self) -> PolicyDecision {
+        let mut reasons = vec![];
+
+        if !self.time_windows.is_empty() {
+            let now = Local::now().time();
+            if !self.time_windows.iter().any(|window| window.contains(now)) {
+                reasons.push(format!("{} is outside the mining time windows", now.format("%H:%M")));
+            }
+        }
+
+        if self.config.require_ac_power && !self.on_ac_power() {
+            reasons.push("running on battery".to_string());
+        }
+
+        if let Some(max_load_average) = self.config.max_load_average {
+            match self.load_average() {
+                Some(load_average) if load_average > max_load_average => reasons.push(format!(
+                    "load average {:.2} is above {:.2}",
+                    load_average, max_load_average
+                )),
+                Some(_) => {},
+                None => warn!(target: LOG_TARGET,
+                    "Mining policy: could not read load average from {}", self.config.loadavg_path.display()
+                ),
+            }
+        }
+
+        PolicyDecision {
+            active: reasons.is_empty(),
+            reasons,
+        }
+    }
+
+    /// Machines that report no mains supply at all, such as most desktops, count as being on AC power
+    fn on_ac_power(&self) -> bool {
+        let power_supply_dir = self.config.sysfs_root.join("class/power_supply");
+        let entries = match fs::read_dir(&power_supply_dir) {
+            Ok(entries) => entries,
+            Err(error) => {
+                warn!(target: LOG_TARGET,
+                    "Mining policy: could not read {}: {}", power_supply_dir.display(), error
+                );
+                return true;
+            },
+        };
+        let mut found_mains = false;
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let path = entry.path();
+            let supply_type = fs::read_to_string(path.join("type")).unwrap_or_default();
+            if supply_type.trim() != "Mains" {
+                continue;
+            }
+            found_mains = true;
+            if fs::read_to_string(path.join("online")).unwrap_or_default().trim() == "1" {
+                return true;
+            }
+        }
+        !found_mains
+    }
+
+    /// One-minute load average
+    fn load_average(&self) -> Option<f64> {
+        fs::read_to_string(&self.config.loadavg_path)
+            .ok()?
+            .split_whitespace()
+            .next()?
+            .parse()
+            .ok()
+    }
+}
+
+fn parse_time(value: &str) -> Result<NaiveTime, anyhow::Error> {
+    NaiveTime::parse_from_str(value.trim(), "%H:%M")
+        .map_err(|error| anyhow!("Invalid mining window time {:?}, expected HH:MM: {}", value, error))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, path::PathBuf, process};
+
+    use super::*;
+
+    /// Power supplies and load average under a temporary directory, laid out like `/sys` and `/proc/loadavg`
+    struct FakeHost {
+        root: PathBuf,
+    }
+
+    impl FakeHost {
+        fn new(test: &str) -> Self {
+            let root = env::temp_dir().join(format!("glytex-policy-{}-{}", test, process::id()));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(root.join("class/power_supply")).unwrap();
+            Self { root }
+        }
+
+        fn power_supply(&self, name: &str, supply_type: &str, online: &str) {
+            let dir = self.root.join("class/power_supply").join(name);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("type"), format!("{}\n", supply_type)).unwrap();
+            fs::write(dir.join("online"), format!("{}\n", online)).unwrap();
+        }
+
+        fn load_average(&self, one_minute: &str) {
+            fs::write(self.loadavg_path(), format!("{} 0.50 0.25 1/234 5678\n", one_minute)).unwrap();
+        }
+
+        fn loadavg_path(&self) -> PathBuf {
+            self.root.join("loadavg")
+        }
+
+        fn policy(&self, require_ac_power: bool, max_load_average: Option<f64>) -> MiningPolicy {
+            MiningPolicy::new(MiningPolicyConfig {
+                require_ac_power,
+                max_load_average,
+                sysfs_root: self.root.clone(),
+                loadavg_path: self.loadavg_path(),
+                ..MiningPolicyConfig::default()
+            })
+            .unwrap()
+        }
+    }
+
+    impl Drop for FakeHost {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    fn time(value: &str) -> NaiveTime {
+        parse_time(value).unwrap()
+    }
+
+    fn window(start: &str, end: &str) -> TimeWindow {
+        TimeWindow {
+            start: time(start),
+            end: time(end),
+        }
+    }
+
+    #[test]
+    fn window_within_a_day() {
+        let window = window("09:00", "17:00");
+        assert!(window.contains(time("09:00")));
+        assert!(window.contains(time("12:30")));
+        assert!(!window.contains(time("17:00")));
+        assert!(!window.contains(time("08:59")));
+        assert!(!window.contains(time("23:00")));
+    }
+
+    #[test]
+    fn window_over_midnight() {
+        let window = window("22:00", "07:00");
+        assert!(window.contains(time("22:00")));
+        assert!(window.contains(time("23:59")));
+        assert!(window.contains(time("00:00")));
+        assert!(window.contains(time("06:59")));
+        assert!(!window.contains(time("07:00")));
+        assert!(!window.contains(time("12:00")));
+        assert!(!window.contains(time("21:59")));
+    }
+
+    #[test]
+    fn rejects_invalid_times() {
+        assert!(parse_time("25:00").is_err());
+        assert!(parse_time("7pm").is_err());
+        assert_eq!(
+            parse_time(" 07:30 ").unwrap(),
+            NaiveTime::from_hms_opt(7, 30, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn on_battery_is_idle() {
+        let host = FakeHost::new("battery");
+        host.power_supply("AC", "Mains", "0");
+        host.power_supply("BAT0", "Battery", "1");
+        let decision = host.policy(true, None).decision();
+        assert!(!decision.active);
+        assert_eq!(decision.reasons, vec!["running on battery".to_string()]);
+
+        // Battery power is fine when the policy doesn't ask for AC
+        assert!(host.policy(false, None).decision().active);
+    }
+
+    #[test]
+    fn on_ac_power_is_active() {
+        let host = FakeHost::new("ac");
+        host.power_supply("AC", "Mains", "1");
+        host.power_supply("BAT0", "Battery", "1");
+        assert!(host.policy(true, None).decision().active);
+    }
+
+    #[test]
+    fn without_a_mains_supply_counts_as_ac_power() {
+        let host = FakeHost::new("desktop");
+        assert!(host.policy(true, None).decision().active);
+    }
+
+    #[test]
+    fn load_above_limit_is_idle() {
+        let host = FakeHost::new("load");
+        host.load_average("3.75");
+        let decision = host.policy(false, Some(2.0)).decision();
+        assert!(!decision.active);
+        assert_eq!(decision.reasons, vec!["load average 3.75 is above 2.00".to_string()]);
+
+        host.load_average("2.00");
+        assert!(host.policy(false, Some(2.0)).decision().active);
+    }
+
+    #[test]
+    fn unreadable_load_average_does_not_stop_mining() {
+        let host = FakeHost::new("noload");
+        assert!(host.policy(false, Some(2.0)).decision().active);
+    }
+}
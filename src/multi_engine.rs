@@ -0,0 +1,276 @@
+use anyhow::{anyhow, Error};
+use log::{info, warn};
+
+use crate::{
+    config_file::ConfigFile, context_impl::ContextImpl, device_info::DeviceInfo, engine_error::EngineError,
+    engine_impl::EngineImpl, engine_type::EngineType, function_impl::FunctionImpl, mine_result::MineResult,
+    LOG_TARGET,
+};
+#[cfg(feature = "cpu")]
+use crate::cpu_engine::{CpuContext, CpuEngine, CpuFunction};
+#[cfg(feature = "nvidia")]
+use crate::cuda_engine::{CudaContext, CudaEngine, CudaFunction};
+#[cfg(feature = "opencl3")]
+use crate::opencl_engine::{OpenClContext, OpenClEngine, OpenClEngineConfig, OpenClFunction};
+
+/// Dispatches to one of the backends compiled into the binary, so a single binary can serve every kind of rig.
+#[derive(Clone)]
+pub enum MultiEngine {
+    #[cfg(feature = "nvidia")]
+    Cuda(CudaEngine),
+    #[cfg(feature = "opencl3")]
+    OpenCl(OpenClEngine),
+    #[cfg(feature = "cpu")]
+    Cpu(CpuEngine),
+}
+
+impl MultiEngine {
+    /// Creates the engine for `engine_type`. With [`EngineType::Auto`] every compiled backend is probed in turn and the
+    /// first one that reports devices is used.
+    pub fn create(engine_type: EngineType, config: &ConfigFile) -> Result<Self, Error> {
+        match engine_type {
+            EngineType::Auto => Self::probe(config),
+            engine_type => Self::create_backend(engine_type, config),
+        }
+    }
+
+    fn create_backend(engine_type: EngineType, config: &ConfigFile) -> Result<Self, Error> {
+        match engine_type {
+            #[cfg(feature = "nvidia")]
+            EngineType::Cuda => Ok(MultiEngine::Cuda(CudaEngine::new(config.cuda_streams))),
+            #[cfg(feature = "opencl3")]
+            EngineType::OpenCl => Ok(MultiEngine::OpenCl(OpenClEngine::new(OpenClEngineConfig {
+                binary_cache_dir: config.opencl_binary_cache_dir(),
+                kernel_path: config.opencl_kernel_path.clone(),
+                build_options: config.opencl_build_options.clone(),
+                defines: config.opencl_defines.clone(),
+                kernel_name: config.opencl_kernel_name.clone(),
+            }))),
+            #[cfg(feature = "cpu")]
+            EngineType::Cpu => Ok(MultiEngine::Cpu(CpuEngine::new(
+                config.cpu_threads.unwrap_or_else(CpuEngine::default_num_threads),
+            ))),
+            engine_type => {
+                let _ = config;
+                Err(anyhow!("glytex was built without support for the {} engine", engine_type))
+            },
+        }
+    }
+
+    fn probe(config: &ConfigFile) -> Result<Self, Error> {
+        for engine_type in Self::compiled_engines() {
+            info!(target: LOG_TARGET, "Probing {} engine", engine_type);
+            let mut engine = Self::create_backend(engine_type, config)?;
+            if let Err(error) = engine.init() {
+                warn!(target: LOG_TARGET, "Could not init {} engine: {:?}", engine_type, error);
+                continue;
+            }
+            match engine.num_devices() {
+                Ok(num_devices) if num_devices > 0 => {
+                    info!(target: LOG_TARGET, "Using {} engine with {} devices", engine_type, num_devices);
+                    return Ok(engine);
+                },
+                Ok(_) => info!(target: LOG_TARGET, "{} engine reported no devices", engine_type),
+                Err(error) => warn!(target: LOG_TARGET, "Could not list {} devices: {:?}", engine_type, error),
+            }
+        }
+        Err(anyhow!("No compiled engine reported any devices"))
+    }
+
+    /// Backends compiled into this binary, in the order they are probed
+    pub fn compiled_engines() -> Vec<EngineType> {
+        let mut engines = vec![];
+        #[cfg(feature = "nvidia")]
+        engines.push(EngineType::Cuda);
+        #[cfg(feature = "opencl3")]
+        engines.push(EngineType::OpenCl);
+        #[cfg(feature = "cpu")]
+        engines.push(EngineType::Cpu);
+        engines
+    }
+
+    pub fn engine_type(&self) -> EngineType {
+        match self {
+            #[cfg(feature = "nvidia")]
+            MultiEngine::Cuda(_) => EngineType::Cuda,
+            #[cfg(feature = "opencl3")]
+            MultiEngine::OpenCl(_) => EngineType::OpenCl,
+            #[cfg(feature = "cpu")]
+            MultiEngine::Cpu(_) => EngineType::Cpu,
+        }
+    }
+}
+
+impl EngineImpl for MultiEngine {
+    type Context = MultiContext;
+    type Function = MultiFunction;
+
+    fn init(&mut self) -> Result<(), EngineError> {
+        match self {
+            #[cfg(feature = "nvidia")]
+            MultiEngine::Cuda(engine) => engine.init(),
+            #[cfg(feature = "opencl3")]
+            MultiEngine::OpenCl(engine) => engine.init(),
+            #[cfg(feature = "cpu")]
+            MultiEngine::Cpu(engine) => engine.init(),
+        }
+    }
+
+    fn num_devices(&self) -> Result<u32, EngineError> {
+        match self {
+            #[cfg(feature = "nvidia")]
+            MultiEngine::Cuda(engine) => engine.num_devices(),
+            #[cfg(feature = "opencl3")]
+            MultiEngine::OpenCl(engine) => engine.num_devices(),
+            #[cfg(feature = "cpu")]
+            MultiEngine::Cpu(engine) => engine.num_devices(),
+        }
+    }
+
+    fn list_devices(&self) -> Result<Vec<DeviceInfo>, EngineError> {
+        match self {
+            #[cfg(feature = "nvidia")]
+            MultiEngine::Cuda(engine) => engine.list_devices(),
+            #[cfg(feature = "opencl3")]
+            MultiEngine::OpenCl(engine) => engine.list_devices(),
+            #[cfg(feature = "cpu")]
+            MultiEngine::Cpu(engine) => engine.list_devices(),
+        }
+    }
+
+    fn create_context(&self, device_index: u32) -> Result<Self::Context, EngineError> {
+        match self {
+            #[cfg(feature = "nvidia")]
+            MultiEngine::Cuda(engine) => Ok(MultiContext::Cuda(engine.create_context(device_index)?)),
+            #[cfg(feature = "opencl3")]
+            MultiEngine::OpenCl(engine) => Ok(MultiContext::OpenCl(engine.create_context(device_index)?)),
+            #[cfg(feature = "cpu")]
+            MultiEngine::Cpu(engine) => Ok(MultiContext::Cpu(engine.create_context(device_index)?)),
+        }
+    }
+
+    fn create_main_function(&self, context: &Self::Context) -> Result<Self::Function, EngineError> {
+        match (self, context) {
+            #[cfg(feature = "nvidia")]
+            (MultiEngine::Cuda(engine), MultiContext::Cuda(context)) => {
+                Ok(MultiFunction::Cuda(engine.create_main_function(context)?))
+            },
+            #[cfg(feature = "opencl3")]
+            (MultiEngine::OpenCl(engine), MultiContext::OpenCl(context)) => {
+                Ok(MultiFunction::OpenCl(engine.create_main_function(context)?))
+            },
+            #[cfg(feature = "cpu")]
+            (MultiEngine::Cpu(engine), MultiContext::Cpu(context)) => {
+                Ok(MultiFunction::Cpu(engine.create_main_function(context)?))
+            },
+            #[allow(unreachable_patterns)]
+            _ => Err(EngineError::EngineMismatch(format!(
+                "context was not created by the {} engine",
+                self.engine_type()
+            ))),
+        }
+    }
+
+    fn mine(
+        &self,
+        function: &Self::Function,
+        context: &Self::Context,
+        data: &[u64],
+        min_difficulty: u64,
+        nonce_start: u64,
+        num_iterations: u32,
+        block_size: u32,
+        grid_size: u32,
+    ) -> Result<MineResult, EngineError> {
+        match (self, function, context) {
+            #[cfg(feature = "nvidia")]
+            (MultiEngine::Cuda(engine), MultiFunction::Cuda(function), MultiContext::Cuda(context)) => engine.mine(
+                function,
+                context,
+                data,
+                min_difficulty,
+                nonce_start,
+                num_iterations,
+                block_size,
+                grid_size,
+            ),
+            #[cfg(feature = "opencl3")]
+            (MultiEngine::OpenCl(engine), MultiFunction::OpenCl(function), MultiContext::OpenCl(context)) => engine
+                .mine(
+                    function,
+                    context,
+                    data,
+                    min_difficulty,
+                    nonce_start,
+                    num_iterations,
+                    block_size,
+                    grid_size,
+                ),
+            #[cfg(feature = "cpu")]
+            (MultiEngine::Cpu(engine), MultiFunction::Cpu(function), MultiContext::Cpu(context)) => engine.mine(
+                function,
+                context,
+                data,
+                min_difficulty,
+                nonce_start,
+                num_iterations,
+                block_size,
+                grid_size,
+            ),
+            #[allow(unreachable_patterns)]
+            _ => Err(EngineError::EngineMismatch(format!(
+                "function or context was not created by the {} engine",
+                self.engine_type()
+            ))),
+        }
+    }
+
+    fn flush(&self, context: &Self::Context) -> Result<Vec<MineResult>, EngineError> {
+        match (self, context) {
+            #[cfg(feature = "nvidia")]
+            (MultiEngine::Cuda(engine), MultiContext::Cuda(context)) => engine.flush(context),
+            #[cfg(feature = "opencl3")]
+            (MultiEngine::OpenCl(engine), MultiContext::OpenCl(context)) => engine.flush(context),
+            #[cfg(feature = "cpu")]
+            (MultiEngine::Cpu(engine), MultiContext::Cpu(context)) => engine.flush(context),
+            #[allow(unreachable_patterns)]
+            _ => Err(EngineError::EngineMismatch(format!(
+                "context was not created by the {} engine",
+                self.engine_type()
+            ))),
+        }
+    }
+}
+
+pub enum MultiContext {
+    #[cfg(feature = "nvidia")]
+    Cuda(CudaContext),
+    #[cfg(feature = "opencl3")]
+    OpenCl(OpenClContext),
+    #[cfg(feature = "cpu")]
+    Cpu(CpuContext),
+}
+
+impl ContextImpl for MultiContext {}
+
+pub enum MultiFunction {
+    #[cfg(feature = "nvidia")]
+    Cuda(CudaFunction),
+    #[cfg(feature = "opencl3")]
+    OpenCl(OpenClFunction),
+    #[cfg(feature = "cpu")]
+    Cpu(CpuFunction),
+}
+
+impl FunctionImpl for MultiFunction {
+    fn suggested_launch_configuration(&self) -> Result<(u32, u32), EngineError> {
+        match self {
+            #[cfg(feature = "nvidia")]
+            MultiFunction::Cuda(function) => function.suggested_launch_configuration(),
+            #[cfg(feature = "opencl3")]
+            MultiFunction::OpenCl(function) => function.suggested_launch_configuration(),
+            #[cfg(feature = "cpu")]
+            MultiFunction::Cpu(function) => function.suggested_launch_configuration(),
+        }
+    }
+}
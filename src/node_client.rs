@@ -75,13 +75,13 @@ impl NodeClient for BaseNodeClientWrapper {
         Ok(NewBlockResult::try_from(res.into_inner())?)
     }
     
-    async fn submit_block(&mut self, block: Block) -> Result<(), anyhow::Error> {
+    async fn submit_block(&mut self, block: Block) -> Result<String, anyhow::Error> {
         info!(target: LOG_TARGET, "Submitting block");
         // dbg!(&block);
         let res = self.client.submit_block(tonic::Request::new(block)).await?;
         println!("Block submitted: {:?}", res);
         info!(target: LOG_TARGET, "Block submitted: {:?}", res);
-        Ok(())
+        Ok(format!("{:?}", res.into_inner()))
     }
 }
 
@@ -93,7 +93,8 @@ pub trait NodeClient {
     
     async fn get_new_block(&mut self, template: NewBlockTemplate) -> Result<NewBlockResult, anyhow::Error>;
     
-    async fn submit_block(&mut self, block: Block) -> Result<(), anyhow::Error>;
+    /// Returns the node's response, for the block history
+    async fn submit_block(&mut self, block: Block) -> Result<String, anyhow::Error>;
 }
 
 pub(crate) async fn create_client(client_type: ClientType, url: &str) -> Result<Client, anyhow::Error> {
@@ -119,6 +120,16 @@ pub enum ClientType {
     P2Pool(TariAddress),
 }
 
+impl ClientType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ClientType::BaseNode => "base_node",
+            ClientType::Benchmark => "benchmark",
+            ClientType::P2Pool(_) => "p2pool",
+        }
+    }
+}
+
 pub struct NewBlockResult {
     pub result: GetNewBlockResult,
     pub target_difficulty: u64,
@@ -165,13 +176,35 @@ impl Client {
         }
     }
 
-    pub async fn submit_block(&mut self, block: Block) -> Result<(), anyhow::Error> {
+    pub async fn submit_block(&mut self, block: Block) -> Result<String, anyhow::Error> {
         match self {
             Client::BaseNode(client) => client.submit_block(block).await,
             Client::Benchmark(client) => client.submit_block(block).await,
             Client::P2Pool(client) => client.submit_block(block).await,
         }
     }
+
+    /// Height of the block the node currently wants mined. A base node reports it in a fresh template, while p2pool
+    /// only hands out whole blocks.
+    pub async fn next_block_height(&mut self) -> Result<u64, anyhow::Error> {
+        let header = match self {
+            Client::BaseNode(client) => client
+                .get_block_template()
+                .await?
+                .new_block_template
+                .and_then(|block_template| block_template.header),
+            Client::P2Pool(client) => client
+                .get_new_block(NewBlockTemplate::default())
+                .await?
+                .result
+                .block
+                .and_then(|block| block.header),
+            Client::Benchmark(_) => return Err(anyhow!("benchmark has no chain")),
+        };
+        header
+            .map(|header| header.height)
+            .ok_or_else(|| anyhow!("block template without a header"))
+    }
 }
 
 pub(crate) struct BenchmarkNodeClient {}
@@ -190,7 +223,7 @@ impl NodeClient for BenchmarkNodeClient {
         todo!()
     }
 
-    async fn submit_block(&mut self, block: Block) -> Result<(), anyhow::Error> {
-        Ok(())
+    async fn submit_block(&mut self, block: Block) -> Result<String, anyhow::Error> {
+        Ok("benchmark, not submitted".to_string())
     }
 }
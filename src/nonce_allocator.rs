@@ -0,0 +1,186 @@
+use std::collections::VecDeque;
+
+use anyhow::anyhow;
+use tari_common_types::types::FixedHash;
+
+const RIG_ID_BITS: u32 = 16;
+const DEVICE_BITS: u32 = 6;
+const OFFSET_BITS: u32 = 64 - RIG_ID_BITS - DEVICE_BITS;
+const MAX_OFFSET: u64 = 1 << OFFSET_BITS;
+/// Devices a single rig can mine on without searching the same nonces
+pub const MAX_DEVICES_PER_RIG: u32 = 1 << DEVICE_BITS;
+/// Jobs besides the current one whose coverage is remembered
+const REMEMBERED_JOBS: usize = 8;
+
+/// Contiguous range of nonces for a single `GpuEngine::mine` call
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NonceChunk {
+    pub start: u64,
+    pub size: u64,
+    /// Job the chunk was handed out for
+    pub epoch: u64,
+}
+
+/// Hands out non-overlapping nonce chunks for one device.
+///
+/// Nonces are laid out as `rig_id (16 bits) | device index (6 bits) | offset (42 bits)`, so rigs with different IDs and
+/// devices within a rig never search the same nonces. Every job switch starts a new epoch. A new mining hash starts at
+/// offset 0, while a re-fetched template with the mining hash of the current or a recent job continues from the
+/// offsets already covered.
+pub struct NonceAllocator {
+    base: u64,
+    epoch: u64,
+    mining_hash: Option<FixedHash>,
+    next_offset: u64,
+    /// Offsets reached by recent jobs, oldest first
+    previous_jobs: VecDeque<(FixedHash, u64)>,
+}
+
+impl NonceAllocator {
+    pub fn new(rig_id: u16, device_index: u32) -> Result<Self, anyhow::Error> {
+        if device_index >= MAX_DEVICES_PER_RIG {
+            return Err(anyhow!(
+                "Device index {} does not fit in the nonce layout, at most {} devices per rig are supported",
+                device_index,
+                MAX_DEVICES_PER_RIG
+            ));
+        }
+        Ok(Self {
+            base: (u64::from(rig_id) << (DEVICE_BITS + OFFSET_BITS)) | (u64::from(device_index) << OFFSET_BITS),
+            epoch: 0,
+            mining_hash: None,
+            next_offset: 0,
+            previous_jobs: VecDeque::new(),
+        })
+    }
+
+    /// Switches to the job for `mining_hash` and returns its epoch. Nothing changes if the mining hash is unchanged.
+    pub fn start_job(&mut self, mining_hash: FixedHash) -> u64 {
+        if self.mining_hash == Some(mining_hash) {
+            return self.epoch;
+        }
+        if let Some(previous_hash) = self.mining_hash.replace(mining_hash) {
+            if self.previous_jobs.len() == REMEMBERED_JOBS {
+                self.previous_jobs.pop_front();
+            }
+            self.previous_jobs.push_back((previous_hash, self.next_offset));
+        }
+        self.next_offset = match self.previous_jobs.iter().position(|(hash, _)| *hash == mining_hash) {
+            Some(position) => self.previous_jobs.remove(position).map_or(0, |(_, offset)| offset),
+            None => 0,
+        };
+        self.epoch += 1;
+        self.epoch
+    }
+
+    /// Reserves the next `size` nonces of the current job, or returns `None` once the job's nonce space is used up
+    pub fn next_chunk(&mut self, size: u64) -> Option<NonceChunk> {
+        if self.mining_hash.is_none() || size == 0 || size > MAX_OFFSET - self.next_offset {
+            return None;
+        }
+        let chunk = NonceChunk {
+            start: self.base | self.next_offset,
+            size,
+            epoch: self.epoch,
+        };
+        self.next_offset += size;
+        Some(chunk)
+    }
+
+    /// Number of nonces handed out for the current job
+    pub fn covered(&self) -> u64 {
+        self.next_offset
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash(byte: u8) -> FixedHash {
+        FixedHash::from([byte; 32])
+    }
+
+    #[test]
+    fn chunks_do_not_overlap() {
+        let mut allocator = NonceAllocator::new(7, 3).unwrap();
+        allocator.start_job(hash(1));
+        let chunks = (0..100).map(|i| allocator.next_chunk(1000 + i).unwrap()).collect::<Vec<_>>();
+        for pair in chunks.windows(2) {
+            assert_eq!(pair[0].start + pair[0].size, pair[1].start);
+        }
+        let base = (7u64 << (DEVICE_BITS + OFFSET_BITS)) | (3 << OFFSET_BITS);
+        assert_eq!(chunks[0].start, base);
+        assert!(chunks.iter().all(|chunk| chunk.start >> OFFSET_BITS == base >> OFFSET_BITS));
+    }
+
+    #[test]
+    fn devices_and_rigs_get_separate_ranges() {
+        let mut first = NonceAllocator::new(1, 0).unwrap();
+        let mut other_device = NonceAllocator::new(1, 1).unwrap();
+        let mut other_rig = NonceAllocator::new(2, 0).unwrap();
+        let starts = [&mut first, &mut other_device, &mut other_rig].map(|allocator| {
+            allocator.start_job(hash(1));
+            allocator.next_chunk(MAX_OFFSET).unwrap().start
+        });
+        assert_ne!(starts[0], starts[1]);
+        assert_ne!(starts[0], starts[2]);
+        assert_ne!(starts[1], starts[2]);
+    }
+
+    #[test]
+    fn needs_a_job_before_handing_out_chunks() {
+        let mut allocator = NonceAllocator::new(0, 0).unwrap();
+        assert_eq!(allocator.next_chunk(10), None);
+    }
+
+    #[test]
+    fn same_mining_hash_continues() {
+        let mut allocator = NonceAllocator::new(0, 0).unwrap();
+        let epoch = allocator.start_job(hash(1));
+        let first = allocator.next_chunk(10).unwrap();
+        assert_eq!(allocator.start_job(hash(1)), epoch);
+        let second = allocator.next_chunk(10).unwrap();
+        assert_eq!(second.start, first.start + 10);
+        assert_eq!(allocator.covered(), 20);
+    }
+
+    #[test]
+    fn new_mining_hash_starts_over() {
+        let mut allocator = NonceAllocator::new(0, 0).unwrap();
+        let epoch = allocator.start_job(hash(1));
+        let first = allocator.next_chunk(10).unwrap();
+        assert_eq!(allocator.start_job(hash(2)), epoch + 1);
+        assert_eq!(allocator.covered(), 0);
+        assert_eq!(allocator.next_chunk(10).unwrap().start, first.start);
+    }
+
+    #[test]
+    fn recent_mining_hash_continues() {
+        let mut allocator = NonceAllocator::new(0, 0).unwrap();
+        allocator.start_job(hash(1));
+        allocator.next_chunk(10).unwrap();
+        allocator.start_job(hash(2));
+        allocator.next_chunk(5).unwrap();
+        allocator.start_job(hash(1));
+        assert_eq!(allocator.covered(), 10);
+        allocator.start_job(hash(2));
+        assert_eq!(allocator.covered(), 5);
+    }
+
+    #[test]
+    fn exhausted_job_hands_out_nothing() {
+        let mut allocator = NonceAllocator::new(0, 0).unwrap();
+        allocator.start_job(hash(1));
+        assert!(allocator.next_chunk(MAX_OFFSET - 1).is_some());
+        assert_eq!(allocator.next_chunk(2), None);
+        assert!(allocator.next_chunk(1).is_some());
+        assert_eq!(allocator.next_chunk(1), None);
+    }
+
+    #[test]
+    fn rejects_device_index_outside_the_layout() {
+        assert!(NonceAllocator::new(0, MAX_DEVICES_PER_RIG - 1).is_ok());
+        assert!(NonceAllocator::new(0, MAX_DEVICES_PER_RIG).is_err());
+    }
+}
@@ -1,22 +1,30 @@
 use std::{
-    io::Read,
+    fs,
+    path::{Path, PathBuf},
     ptr,
-    sync::{Arc, RwLock},
+    sync::{Arc, Mutex, RwLock},
+    time::Instant,
 };
 
-use anyhow::Error;
 use opencl3::{
     command_queue::{CommandQueue, CL_QUEUE_PROFILING_ENABLE},
     context::Context,
     device::{Device, CL_DEVICE_TYPE_GPU},
+    error_codes::ClError,
     kernel::{ExecuteKernel, Kernel},
-    memory::{Buffer, CL_MEM_READ_ONLY, CL_MEM_WRITE_ONLY},
+    memory::{Buffer, CL_MEM_READ_ONLY, CL_MEM_READ_WRITE},
     platform::{get_platforms, Platform},
     program::Program,
     types::{cl_ulong, CL_TRUE},
 };
-use crate::{context_impl::ContextImpl, engine_impl::EngineImpl, function_impl::FunctionImpl};
-use log::{error, info, warn};
+use sha3::{Digest, Sha3_256};
+use tari_utilities::hex::to_hex;
+use crate::{
+    context_impl::ContextImpl, device_info::DeviceInfo, engine_error::EngineError, engine_impl::EngineImpl,
+    engine_type::EngineType, function_impl::FunctionImpl,
+    mine_result::{MineResult, DATA_WORDS, MAX_SOLUTIONS, OUTPUT_WORDS},
+};
+use log::{debug, error, info, warn};
 
 const LOG_TARGET: &str = "tari::universe::gpu_miner";//TODO set log target
 
@@ -24,15 +32,30 @@ pub struct OpenClEngineInner {
     platforms: Vec<Platform>,
 }
 
+/// Where the kernel comes from and how it is built
+#[derive(Clone)]
+pub struct OpenClEngineConfig {
+    /// Directory holding compiled program binaries, so the kernel is only built from source once per device
+    pub binary_cache_dir: PathBuf,
+    /// Kernel source to build instead of the built-in opencl_sha3.cl
+    pub kernel_path: Option<PathBuf>,
+    pub build_options: String,
+    /// Passed to the compiler as `-D <define>`
+    pub defines: Vec<String>,
+    pub kernel_name: String,
+}
+
 #[derive(Clone)]
 pub struct OpenClEngine {
     inner: Arc<RwLock<OpenClEngineInner>>,
+    config: OpenClEngineConfig,
 }
 
 impl OpenClEngine {
-    pub fn new() -> Self {
+    pub fn new(config: OpenClEngineConfig) -> Self {
         OpenClEngine {
             inner: Arc::new(RwLock::new(OpenClEngineInner { platforms: vec![] })),
+            config,
         }
     }
 }
@@ -41,50 +64,80 @@ impl EngineImpl for OpenClEngine {
     type Context = OpenClContext;
     type Function = OpenClFunction;
 
-    fn init(&mut self) -> Result<(), anyhow::Error> {
+    fn init(&mut self) -> Result<(), EngineError> {
         info!(target: LOG_TARGET, "OpenClEngine: init engine");
-        let platforms = get_platforms()?;
+        let platforms = get_platforms().map_err(|e| EngineError::Init(e.to_string()))?;
         let mut lock = self.inner.write().unwrap();
         lock.platforms = platforms;
         Ok(())
     }
     
-    fn num_devices(&self) -> Result<u32, anyhow::Error> {
+    fn num_devices(&self) -> Result<u32, EngineError> {
         info!(target: LOG_TARGET, "OpenClEngine: num_devices");
         let mut total_devices = 0;
         let lock = self.inner.read().unwrap();
         for platform in lock.platforms.iter() {
-            let devices = platform.get_devices(CL_DEVICE_TYPE_GPU)?;
-            info!(target: LOG_TARGET, "OpenClEngine: platform name: {}", platform.name()?);
-            println!("Platform: {}", platform.name()?);
-            info!(target: LOG_TARGET, "OpenClEngine: devices:");
-            println!("Devices: ");
-            for device in devices {
+            total_devices += platform
+                .get_devices(CL_DEVICE_TYPE_GPU)
+                .map_err(device_query_error)?
+                .len() as u32;
+        }
+        Ok(total_devices)
+    }
+
+    fn list_devices(&self) -> Result<Vec<DeviceInfo>, EngineError> {
+        info!(target: LOG_TARGET, "OpenClEngine: list devices");
+        let mut devices = vec![];
+        let lock = self.inner.read().unwrap();
+        for platform in lock.platforms.iter() {
+            let platform_name = platform.name().map_err(device_query_error)?;
+            for device in platform.get_devices(CL_DEVICE_TYPE_GPU).map_err(device_query_error)? {
                 let dev = Device::new(device);
-                info!(target: LOG_TARGET, "Device: {}", dev.name()?);
-                println!("Device: {}", dev.name()?);
-                total_devices += 1;
+                devices.push(DeviceInfo {
+                    index: devices.len() as u32,
+                    backend: EngineType::OpenCl,
+                    platform_name: platform_name.clone(),
+                    device_name: dev.name().map_err(device_query_error)?,
+                    vendor: dev.vendor().map_err(device_query_error)?,
+                    driver_version: dev.driver_version().map_err(device_query_error)?,
+                    compute_units: dev.max_compute_units().map_err(device_query_error)?,
+                    max_work_group_size: dev.max_work_group_size().map_err(device_query_error)? as u64,
+                    global_memory: dev.global_mem_size().map_err(device_query_error)?,
+                    launch_configuration: None,
+                });
             }
         }
-        Ok(total_devices)
+        Ok(devices)
     }
 
-    fn create_context(&self, device_index: u32) -> Result<Self::Context, anyhow::Error> {
+    fn create_context(&self, device_index: u32) -> Result<Self::Context, EngineError> {
         info!(target: LOG_TARGET, "OpenClEngine: create context");
         let lock = self.inner.write().unwrap();
         let mut devices = vec![];
         for platform in lock.platforms.iter() {
-            devices.extend_from_slice(&platform.get_devices(CL_DEVICE_TYPE_GPU)?);
+            devices.extend_from_slice(&platform.get_devices(CL_DEVICE_TYPE_GPU).map_err(device_query_error)?);
         }
-        let device = devices[device_index as usize];
-        let context = Context::from_device(&Device::new(device))?;
-        Ok(OpenClContext::new(context))
+        let device = devices
+            .get(device_index as usize)
+            .ok_or(EngineError::DeviceNotFound(device_index))?;
+        let context = Context::from_device(&Device::new(*device)).map_err(context_error)?;
+        OpenClContext::new(context)
     }
     
-    fn create_main_function(&self, context: &Self::Context) -> Result<Self::Function, anyhow::Error> {
+    fn create_main_function(&self, context: &Self::Context) -> Result<Self::Function, EngineError> {
         info!(target: LOG_TARGET, "OpenClEngine: create function");
-        let program = create_program_from_source(&context.context).unwrap();
-        Ok(OpenClFunction { program })
+        let program = create_program(&context.context, &self.config)?;
+        let kernel = Kernel::create(&program, &self.config.kernel_name).map_err(|e| EngineError::Compile {
+            message: format!("could not create kernel {}: {}", self.config.kernel_name, e),
+            build_log: String::new(),
+        })?;
+        let device = *context.context.devices().first().ok_or(EngineError::DeviceNotFound(0))?;
+        let max_block_size = kernel.get_work_group_size(device).map_err(device_query_error)?;
+        Ok(OpenClFunction {
+            program,
+            kernel,
+            max_block_size: u32::try_from(max_block_size).unwrap_or(u32::MAX),
+        })
     }
 
     fn mine(
@@ -97,69 +150,165 @@ impl EngineImpl for OpenClEngine {
         num_iterations: u32,
         block_size: u32,
         grid_size: u32,
-    ) -> Result<(Option<u64>, u32, u64), Error> {
-        // TODO: put in multiple threads
-        info!(target: LOG_TARGET, "OpenClEngine: mine");
-        
-        let kernels = vec![Kernel::create(&function.program, "sha3").expect("bad kernel")];
-
-        //  let queue = CommandQueue::create_default_with_properties(
-        //     &context.context,
-        //     CL_QUEUE_OUT_OF_ORDER_EXEC_MODE_ENABLE,
-        //     0
-        // )?;
+    ) -> Result<MineResult, EngineError> {
+        if block_size > function.max_block_size {
+            return Err(EngineError::Launch(format!(
+                "block size {} exceeds the kernel's work group size limit of {}",
+                block_size, function.max_block_size
+            )));
+        }
+        let mut buffer = context.buffer.lock().unwrap();
+        let mut output_buffer = context.output_buffer.lock().unwrap();
+        let mut initial_output = [0u64; OUTPUT_WORDS];
+        initial_output[1] = u64::MAX;
+        let mut output = [0u64; OUTPUT_WORDS];
+        let timer = Instant::now();
         unsafe {
-            let queue = CommandQueue::create_default(&context.context, CL_QUEUE_PROFILING_ENABLE)
-                .expect("could not create command queue");
-
-            let batch_size = 1 << 19; // According to tests, but we can try work this out
-            let global_dimensions = [batch_size as usize];
-            let max_workgroups = Device::new(context.context.devices()[0]).max_work_group_size().unwrap();
-            // dbg!(max_compute);
-            // let max_work_items = queue.max_work_item_dimensions();
-            // dbg!(max_work_items);
-            // dbg!("here");
-            let mut buffer =
-                Buffer::<cl_ulong>::create(&context.context, CL_MEM_READ_ONLY, data.len(), ptr::null_mut())?;
-            queue.enqueue_write_buffer(&mut buffer, CL_TRUE, 0, data, &[])?;
-            let output_buffer = Buffer::<cl_ulong>::create(&context.context, CL_MEM_WRITE_ONLY, 2, ptr::null_mut())?;
-            // dbg!(block_size);
-            // dbg!(grid_size);
-            for kernel in kernels {
-                ExecuteKernel::new(&kernel)
-            .set_arg(&buffer)
-            .set_arg(&nonce_start)
-            .set_arg(&min_difficulty)
-            .set_arg(&num_iterations)
-            .set_arg(&output_buffer)
-
-            .set_global_work_size((grid_size * block_size) as usize)
-            // .set_local_work_size(max_workgroups)
-            // .set_wait_event(&y_write_event)
-            .enqueue_nd_range(&queue).expect("culd not queue");
-
-                // TODO: find out better workdim
-                // queue.enqueue_nd_range_kernel(kernel.get(), 1, 0 as *const usize, global_dimensions.as_ptr(), 0 as
-                // *const usize, &[]).expect("could not execute");
-            }
-            queue.finish()?;
-            let mut output = vec![0u64, 0u64];
-            queue.enqueue_read_buffer(&output_buffer, CL_TRUE, 0, output.as_mut_slice(), &[])?;
-            if output[0] > 0 {
-                return Ok((
-                    Some(output[0]),
-                    grid_size * block_size * num_iterations,
-                    u64::MAX / output[1],
-                ));
-            }
-            return Ok((None, grid_size * block_size * num_iterations, u64::MAX / output[1]));
+            // Blocking writes, so an early return below can't leave the driver reading `data` or `initial_output`
+            // after they are freed. Both buffers are a few words, so waiting costs nothing
+            context
+                .queue
+                .enqueue_write_buffer(&mut buffer, CL_TRUE, 0, &data[..DATA_WORDS], &[])
+                .map_err(launch_error)?;
+            context
+                .queue
+                .enqueue_write_buffer(&mut output_buffer, CL_TRUE, 0, &initial_output, &[])
+                .map_err(launch_error)?;
+            ExecuteKernel::new(&function.kernel)
+                .set_arg(&*buffer)
+                .set_arg(&nonce_start)
+                .set_arg(&min_difficulty)
+                .set_arg(&num_iterations)
+                .set_arg(&*output_buffer)
+                .set_global_work_size(grid_size as usize * block_size as usize)
+                .set_local_work_size(block_size as usize)
+                .enqueue_nd_range(&context.queue)
+                .map_err(launch_error)?;
+            context.queue.finish().map_err(launch_error)?;
+            context
+                .queue
+                .enqueue_read_buffer(&output_buffer, CL_TRUE, 0, &mut output, &[])
+                .map_err(|e| EngineError::Readback(e.to_string()))?;
         }
-        Ok((None, grid_size * block_size * num_iterations, 0))
+        Ok(MineResult::from_kernel_output(
+            &output,
+            u64::from(grid_size) * u64::from(block_size) * u64::from(num_iterations),
+            timer.elapsed(),
+        ))
     }
 }
-fn create_program_from_source(context: &Context) -> Option<Program> {
-    let opencl_code = include_str!("./opencl_sha3.cl");
-    info!(target: LOG_TARGET, "OpenClEngine: create program from source. Opencl code: {}", &opencl_code);
+/// Loads the program from the binary cache if it holds a build for this device, driver, kernel source and build
+/// options, otherwise builds it from source and refreshes the cache.
+fn create_program(context: &Context, config: &OpenClEngineConfig) -> Result<Program, EngineError> {
+    let opencl_code = match &config.kernel_path {
+        Some(kernel_path) => {
+            info!(target: LOG_TARGET, "OpenClEngine: using kernel source {}", kernel_path.display());
+            fs::read_to_string(kernel_path).map_err(|e| EngineError::Compile {
+                message: format!("could not read kernel source {}: {}", kernel_path.display(), e),
+                build_log: String::new(),
+            })?
+        },
+        None => include_str!("./opencl_sha3.cl").to_string(),
+    };
+    let build_options = build_options(config);
+    let cache_path = match binary_cache_path(context, &config.binary_cache_dir, &opencl_code, &build_options) {
+        Ok(cache_path) => Some(cache_path),
+        Err(error) => {
+            warn!(target: LOG_TARGET, "OpenClEngine: could not determine program binary cache path: {}", error);
+            None
+        },
+    };
+
+    if let Some(cache_path) = cache_path.as_ref().filter(|path| path.exists()) {
+        match create_program_from_binary(context, cache_path, &build_options) {
+            Ok(program) => {
+                info!(target: LOG_TARGET, "OpenClEngine: program loaded from {}", cache_path.display());
+                return Ok(program);
+            },
+            Err(error) => {
+                warn!(target: LOG_TARGET,
+                    "OpenClEngine: cached program {} is unusable, building from source: {:?}",
+                    cache_path.display(),
+                    error
+                );
+            },
+        }
+    }
+
+    let program = create_program_from_source(context, &opencl_code, &build_options)?;
+    if let Some(cache_path) = cache_path {
+        match save_program_binary(&program, &cache_path) {
+            Ok(()) => info!(target: LOG_TARGET, "OpenClEngine: program binary cached at {}", cache_path.display()),
+            Err(error) => warn!(target: LOG_TARGET,
+                "OpenClEngine: could not cache program binary at {}: {:?}", cache_path.display(), error
+            ),
+        }
+    }
+    Ok(program)
+}
+
+/// `MAX_SOLUTIONS` is always defined, as the host reads back a ring of exactly that size
+fn build_options(config: &OpenClEngineConfig) -> String {
+    let mut options = vec![format!("-D MAX_SOLUTIONS={}", MAX_SOLUTIONS)];
+    options.extend(config.defines.iter().map(|define| format!("-D {}", define)));
+    if !config.build_options.is_empty() {
+        options.push(config.build_options.clone());
+    }
+    options.join(" ")
+}
+
+/// Cache entries are named after a hash of everything that affects the compiled binary
+fn binary_cache_path(
+    context: &Context,
+    binary_cache_dir: &Path,
+    source: &str,
+    build_options: &str,
+) -> Result<PathBuf, ClError> {
+    let device = Device::new(context.default_device());
+    let mut hasher = Sha3_256::new();
+    hasher.update(device.name()?);
+    hasher.update([0u8]);
+    hasher.update(device.driver_version()?);
+    hasher.update([0u8]);
+    hasher.update(build_options);
+    hasher.update([0u8]);
+    hasher.update(source);
+    Ok(binary_cache_dir.join(format!("{}.bin", to_hex(hasher.finalize().as_slice()))))
+}
+
+fn create_program_from_binary(context: &Context, path: &Path, build_options: &str) -> Result<Program, anyhow::Error> {
+    let binary = fs::read(path)?;
+    let mut program = unsafe { Program::create_from_binary(context, context.devices(), &[binary.as_slice()])? };
+    program.build(context.devices(), build_options)?;
+    Ok(program)
+}
+
+fn save_program_binary(program: &Program, path: &Path) -> Result<(), anyhow::Error> {
+    let binaries = program.get_binaries()?;
+    let binary = binaries
+        .first()
+        .ok_or_else(|| anyhow::anyhow!("program has no binaries"))?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // Devices of the same model build the same entry at the same time, so the binary is written next to its final
+    // path and renamed into place, and readers never see a partial file
+    let temp_path = path.with_extension(format!("{:016x}.tmp", rand::random::<u64>()));
+    fs::write(&temp_path, binary)?;
+    if let Err(error) = fs::rename(&temp_path, path) {
+        let _ = fs::remove_file(&temp_path);
+        return Err(error.into());
+    }
+    Ok(())
+}
+
+fn create_program_from_source(
+    context: &Context,
+    opencl_code: &str,
+    build_options: &str,
+) -> Result<Program, EngineError> {
+    info!(target: LOG_TARGET, "OpenClEngine: create program from source");
+    debug!(target: LOG_TARGET, "OpenClEngine: opencl code: {}", opencl_code);
     // Load the program from file.
     let mut program = match Program::create_from_source(&context, &opencl_code) {
         Ok(program) => {
@@ -169,24 +318,29 @@ fn create_program_from_source(context: &Context) -> Option<Program> {
         Err(error) => {
             error!(target: LOG_TARGET, "OpenClEngine: program creating error : {}", error);
             println!("Programing creating error : {}", error);
-            unimplemented!("");
+            return Err(EngineError::Compile {
+                message: error.to_string(),
+                build_log: String::new(),
+            });
         },
     };
     
     // Build the program.
-    match program.build(context.devices(), "") {
+    match program.build(context.devices(), build_options) {
         Ok(_) =>{
             info!(target: LOG_TARGET, "OpenClEngine: program built successfully");
-            Some(program)
+            Ok(program)
         } 
         Err(error) => {
             error!(target: LOG_TARGET, "OpenClEngine: program building error : {}", error);
             println!("Program building error : {}", error);
+            let mut build_log = String::new();
             for device_id in context.devices() {
                 match program.get_build_log(*device_id) {
                     Ok(log) => {
                         info!(target: LOG_TARGET, "OpenClEngine: program log {}", log);
-                        println!("{}", log)
+                        println!("{}", log);
+                        build_log.push_str(&log);
                     }
                     Err(error) => {
                         error!(target: LOG_TARGET, "OpenClEngine: error getting the build log : {}", error);
@@ -194,28 +348,67 @@ fn create_program_from_source(context: &Context) -> Option<Program> {
                     } 
                 };
             }
-            None
+            Err(EngineError::Compile {
+                message: error.to_string(),
+                build_log,
+            })
         },
     }
 }
 
+/// Holds everything `mine` needs per device, so nothing is created in the mining loop
 pub struct OpenClContext {
     context: Context,
+    queue: CommandQueue,
+    buffer: Mutex<Buffer<cl_ulong>>,
+    output_buffer: Mutex<Buffer<cl_ulong>>,
 }
 
 impl OpenClContext {
-    pub fn new(context: Context) -> Self {
-        OpenClContext { context }
+    pub fn new(context: Context) -> Result<Self, EngineError> {
+        let queue = CommandQueue::create_default(&context, CL_QUEUE_PROFILING_ENABLE).map_err(context_error)?;
+        let buffer = unsafe {
+            Buffer::<cl_ulong>::create(&context, CL_MEM_READ_ONLY, DATA_WORDS, ptr::null_mut()).map_err(context_error)?
+        };
+        let output_buffer = unsafe {
+            Buffer::<cl_ulong>::create(&context, CL_MEM_READ_WRITE, OUTPUT_WORDS, ptr::null_mut())
+                .map_err(context_error)?
+        };
+        Ok(OpenClContext {
+            context,
+            queue,
+            buffer: Mutex::new(buffer),
+            output_buffer: Mutex::new(output_buffer),
+        })
     }
 }
 
 impl ContextImpl for OpenClContext {}
 
 pub struct OpenClFunction {
+    // The kernel references the program, so it is kept alive alongside it
+    #[allow(dead_code)]
     program: Program,
+    kernel: Kernel,
+    /// Largest work group the kernel can be launched with on this device
+    max_block_size: u32,
 }
 impl FunctionImpl for OpenClFunction {
-    fn suggested_launch_configuration(&self) -> Result<(u32, u32), anyhow::Error> {
-        Ok((1000, 1000))
+    fn suggested_launch_configuration(&self) -> Result<(u32, u32), EngineError> {
+        // A million work items per launch, in work groups the device supports
+        let block_size = self.max_block_size.clamp(1, 1000);
+        Ok((1_000_000 / block_size, block_size))
     }
 }
+
+fn device_query_error(error: ClError) -> EngineError {
+    EngineError::DeviceQuery(error.to_string())
+}
+
+fn context_error(error: ClError) -> EngineError {
+    EngineError::Context(error.to_string())
+}
+
+fn launch_error(error: ClError) -> EngineError {
+    EngineError::Launch(error.to_string())
+}
@@ -1,3 +1,13 @@
+#ifdef cl_khr_int64_base_atomics
+#pragma OPENCL EXTENSION cl_khr_int64_base_atomics : enable
+#endif
+
+// Number of (nonce, hash) slots in the output ring. The host passes the value it
+// allocated for with -D MAX_SOLUTIONS.
+#ifndef MAX_SOLUTIONS
+#define MAX_SOLUTIONS 16
+#endif
+
 constant static const ulong rot[24] = {1,  3,  6,  10, 15, 21, 28, 36,
                                        45, 55, 2,  14, 27, 41, 56, 8,
                                        25, 43, 62, 18, 39, 61, 20, 44};
@@ -30,6 +40,11 @@ ulong swap_endian_64(ulong value) {
 }
 
 
+// output_1 layout:
+//   [0] number of qualifying nonces found (may exceed MAX_SOLUTIONS), counted in
+//       its low 32 bits so devices without 64-bit atomics can run the kernel
+//   [1] lowest hash seen, initialised to ULONG_MAX by the host
+//   [2 + 2 * n], [3 + 2 * n] nonce and hash of solution n
 kernel void sha3(global ulong *buffer,
                  ulong nonce_start, ulong difficulty,
                  uint num_rounds, global ulong *output_1
@@ -41,7 +56,8 @@ for (uint i = 0;i< num_rounds; i++) {
   for (uint j = 0; j < 25; j++) {
     state[j] = 0;
   }
-  state[0] = nonce_start + get_global_id(0) + i * get_global_size(0);
+  ulong nonce = nonce_start + get_global_id(0) + i * get_global_size(0);
+  state[0] = nonce;
   state[1] = buffer[1];
   state[2] = buffer[2];
   state[3] = buffer[3];
@@ -157,17 +173,27 @@ for (uint i = 0;i< num_rounds; i++) {
      // check difficulty
      ulong swap = swap_endian_64(state[0]);
      if (swap < difficulty) {
-       output_1[0] = nonce_start + get_global_id(0) + i * get_global_size(0);
-       output_1[1] = swap; 
+       uint index = atomic_inc((volatile global uint *)&output_1[0]);
+       if (index < MAX_SOLUTIONS) {
+         output_1[2 + index * 2] = nonce;
+         output_1[3 + index * 2] = swap;
+       }
+     }
+#ifdef cl_khr_int64_base_atomics
+     ulong best = output_1[1];
+     while (swap < best) {
+       ulong previous = atom_cmpxchg(&output_1[1], best, swap);
+       if (previous == best) {
+         break;
+       }
+       best = previous;
      }
-     else {
-      if (output_1[1] == 0 || output_1[1] > swap) {
-          output_1[1] = swap;
-      }
-     // if (output_1[1] < nonce_start+ get_global_id(0)) {
-     //   output_1[1] = nonce_start + get_global_id(0);
-     // }
+#else
+     // Racy, but the best hash only feeds stats
+     if (output_1[1] > swap) {
+       output_1[1] = swap;
      }
+#endif
 
      //output_1[0] = difficulty;
      //  output_1[0] = nonce_start + get_global_id(0) ;
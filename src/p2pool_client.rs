@@ -70,14 +70,14 @@ impl NodeClient for P2poolClientWrapper {
         })
     }
 
-async fn submit_block(&mut self, block: Block) -> Result<(), Error> {
+async fn submit_block(&mut self, block: Block) -> Result<String, Error> {
         info!(target: LOG_TARGET, "P2poolClientWrapper: submitting block");
-        self.client
+        let response = self.client
             .submit_block(SubmitBlockRequest {
                 block: Some(block),
                 wallet_payment_address: self.wallet_payment_address.to_base58(),
             })
             .await?;
-        Ok(())
+        Ok(format!("{:?}", response.into_inner()))
     }
 }
@@ -1,35 +1,262 @@
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    block_history::{BlockHistory, BlockSubmission, NewBlockSubmission, SubmissionResult},
+    mining_policy::PolicyDecision,
+    supervisor::DeviceStatus,
+    thermal::ThermalReading,
+};
+
+/// Longest rolling window, samples older than this are dropped
+const MAX_WINDOW: Duration = Duration::from_secs(15 * 60);
+/// Hash counts recorded within this interval of the previous sample are merged into it
+const SAMPLE_RESOLUTION: Duration = Duration::from_secs(1);
+
+/// Hashes per second over rolling windows
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct RollingHashrate {
+    pub last_10s: u64,
+    pub last_60s: u64,
+    pub last_15m: u64,
+    /// Since the device started mining, including time spent paused or idle
+    pub session_average: u64,
+}
+
+impl RollingHashrate {
+    fn add(&mut self, other: &RollingHashrate) {
+        self.last_10s += other.last_10s;
+        self.last_60s += other.last_60s;
+        self.last_15m += other.last_15m;
+        self.session_average += other.session_average;
+    }
+}
+
+/// Mining counters of a single device
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DeviceStats {
+    pub hashes: u64,
+    pub hashes_per_second: u64,
+    pub hashrate: RollingHashrate,
+    /// Solutions that passed host-side verification
+    pub solutions: u64,
+    /// Nonces returned by the device that failed host-side verification
+    pub invalid_solutions: u64,
+    pub errors: u64,
+}
+
+struct DeviceCounters {
+    stats: DeviceStats,
+    started: Option<Instant>,
+    /// Hashes completed, by the time they were recorded, covering the last [`MAX_WINDOW`]
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl DeviceCounters {
+    fn new() -> Self {
+        Self {
+            stats: DeviceStats::default(),
+            started: None,
+            samples: VecDeque::new(),
+        }
+    }
+
+    fn record(&mut self, hashes: u64, now: Instant) {
+        self.started.get_or_insert(now);
+        self.stats.hashes += hashes;
+        match self.samples.back_mut() {
+            Some((recorded, sample_hashes)) if now.duration_since(*recorded) < SAMPLE_RESOLUTION => {
+                *sample_hashes += hashes
+            },
+            _ => self.samples.push_back((now, hashes)),
+        }
+        while let Some((recorded, _)) = self.samples.front() {
+            if now.duration_since(*recorded) <= MAX_WINDOW {
+                break;
+            }
+            self.samples.pop_front();
+        }
+    }
+
+    fn hashrate(&self, now: Instant) -> RollingHashrate {
+        let Some(started) = self.started else {
+            return RollingHashrate::default();
+        };
+        let session = now.duration_since(started);
+        RollingHashrate {
+            last_10s: self.window_hashrate(now, session, Duration::from_secs(10)),
+            last_60s: self.window_hashrate(now, session, Duration::from_secs(60)),
+            last_15m: self.window_hashrate(now, session, MAX_WINDOW),
+            session_average: per_second(self.stats.hashes, session),
+        }
+    }
+
+    /// A window longer than the session so far is shortened to the session, so a device that just started does not
+    /// report a fraction of its real hashrate
+    fn window_hashrate(&self, now: Instant, session: Duration, window: Duration) -> u64 {
+        let hashes = self
+            .samples
+            .iter()
+            .rev()
+            .take_while(|(recorded, _)| now.duration_since(*recorded) <= window)
+            .map(|(_, hashes)| hashes)
+            .sum();
+        per_second(hashes, window.min(session))
+    }
+}
+
+fn per_second(hashes: u64, duration: Duration) -> u64 {
+    let seconds = duration.as_secs_f64();
+    if seconds > 0.0 {
+        (hashes as f64 / seconds) as u64
+    } else {
+        0
+    }
+}
+
+/// Number and total duration of calls to a node, exported as a Prometheus summary
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Latency {
+    pub count: u64,
+    pub total: Duration,
+}
+
+impl Latency {
+    fn record(&mut self, latency: Duration) {
+        self.count += 1;
+        self.total += latency;
+    }
+}
 
 /// Stats store stores statistics about running miner in memory.
 pub struct StatsStore {
-    hashes_per_second: AtomicU64,
+    started: Instant,
     accepted_blocks: AtomicU64,
     rejected_blocks: AtomicU64,
+    stale_blocks: AtomicU64,
+    target_difficulty: AtomicU64,
+    block_height: AtomicU64,
+    template_fetch_latency: RwLock<Latency>,
+    submit_latency: RwLock<Latency>,
+    block_history: RwLock<BlockHistory>,
+    device_stats: RwLock<BTreeMap<u32, DeviceCounters>>,
+    thermal: RwLock<BTreeMap<u32, ThermalReading>>,
+    policy: RwLock<PolicyDecision>,
+    devices: RwLock<BTreeMap<u32, DeviceStatus>>,
+    unhealthy_devices: RwLock<BTreeSet<u32>>,
 }
 
 impl StatsStore {
     pub fn new() -> Self {
         Self {
-            hashes_per_second: AtomicU64::new(0),
+            started: Instant::now(),
             accepted_blocks: AtomicU64::new(0),
             rejected_blocks: AtomicU64::new(0),
+            stale_blocks: AtomicU64::new(0),
+            target_difficulty: AtomicU64::new(0),
+            block_height: AtomicU64::new(0),
+            template_fetch_latency: RwLock::new(Latency::default()),
+            submit_latency: RwLock::new(Latency::default()),
+            block_history: RwLock::new(BlockHistory::new()),
+            device_stats: RwLock::new(BTreeMap::new()),
+            thermal: RwLock::new(BTreeMap::new()),
+            policy: RwLock::new(PolicyDecision::default()),
+            devices: RwLock::new(BTreeMap::new()),
+            unhealthy_devices: RwLock::new(BTreeSet::new()),
         }
     }
 
-    pub fn update_hashes_per_second(&self, new_value: u64) {
-        self.hashes_per_second.store(new_value, Ordering::SeqCst);
+    /// Starts the session of a device, which the session average and the rolling windows are measured from.
+    /// Calling it again, e.g. after the device worker restarts, keeps the original session.
+    pub fn start_device(&self, device_index: u32) {
+        self.update_device_stats(device_index, |counters| {
+            counters.started.get_or_insert_with(Instant::now);
+        });
     }
 
-    pub fn inc_accepted_blocks(&self) {
-        self.accepted_blocks.fetch_add(1, Ordering::SeqCst);
+    /// Records the hashes of a finished `mine` call
+    pub fn add_hashes(&self, device_index: u32, hashes: u64) {
+        let now = Instant::now();
+        self.update_device_stats(device_index, |counters| counters.record(hashes, now));
     }
 
-    pub fn inc_rejected_blocks(&self) {
-        self.accepted_blocks.fetch_add(1, Ordering::SeqCst);
+    pub fn update_hashes_per_second(&self, device_index: u32, new_value: u64) {
+        self.update_device_stats(device_index, |counters| counters.stats.hashes_per_second = new_value);
     }
 
+    pub fn inc_solutions(&self, device_index: u32) {
+        self.update_device_stats(device_index, |counters| counters.stats.solutions += 1);
+    }
+
+    pub fn inc_errors(&self, device_index: u32) {
+        self.update_device_stats(device_index, |counters| counters.stats.errors += 1);
+    }
+
+    /// Adds a submission to the block history and counts it as accepted, rejected or stale
+    pub fn record_submission(&self, submission: NewBlockSubmission) {
+        let counter = match submission.result {
+            SubmissionResult::Accepted => &self.accepted_blocks,
+            SubmissionResult::Rejected => &self.rejected_blocks,
+            SubmissionResult::Stale => &self.stale_blocks,
+        };
+        counter.fetch_add(1, Ordering::SeqCst);
+        self.block_history.write().unwrap().push(submission);
+    }
+
+    /// Records a freshly fetched template. The block height only ever grows, so a device still mining on an older
+    /// template does not move it backwards.
+    pub fn record_template(&self, height: u64, target_difficulty: u64, latency: Duration) {
+        self.block_height.fetch_max(height, Ordering::SeqCst);
+        self.target_difficulty.store(target_difficulty, Ordering::SeqCst);
+        self.template_fetch_latency.write().unwrap().record(latency);
+    }
+
+    pub fn record_submit_latency(&self, latency: Duration) {
+        self.submit_latency.write().unwrap().record(latency);
+    }
+
+    /// Counts a nonce returned by the device that failed host-side verification
+    pub fn inc_invalid_solutions(&self, device_index: u32) {
+        self.update_device_stats(device_index, |counters| counters.stats.invalid_solutions += 1);
+    }
+
+    pub fn update_thermal(&self, device_index: u32, reading: ThermalReading) {
+        self.thermal.write().unwrap().insert(device_index, reading);
+    }
+
+    pub fn update_policy(&self, decision: PolicyDecision) {
+        *self.policy.write().unwrap() = decision;
+    }
+
+    pub fn update_device_status(&self, device_index: u32, status: DeviceStatus) {
+        self.devices.write().unwrap().insert(device_index, status);
+    }
+
+    pub fn set_device_healthy(&self, device_index: u32, healthy: bool) {
+        let mut lock = self.unhealthy_devices.write().unwrap();
+        if healthy {
+            lock.remove(&device_index);
+        } else {
+            lock.insert(device_index);
+        }
+    }
+
+    /// Total hashrate across all devices
     pub fn hashes_per_second(&self) -> u64 {
-        self.hashes_per_second.load(Ordering::SeqCst)
+        self.device_stats
+            .read()
+            .unwrap()
+            .values()
+            .map(|counters| counters.stats.hashes_per_second)
+            .sum()
     }
 
     pub fn accepted_blocks(&self) -> u64 {
@@ -39,4 +266,183 @@ impl StatsStore {
     pub fn rejected_blocks(&self) -> u64 {
         self.rejected_blocks.load(Ordering::SeqCst)
     }
+
+    pub fn stale_blocks(&self) -> u64 {
+        self.stale_blocks.load(Ordering::SeqCst)
+    }
+
+    /// Returns the number of submissions in the history, and page `page` of it, newest first
+    pub fn block_submissions(&self, page: usize, page_size: usize) -> (usize, Vec<BlockSubmission>) {
+        let block_history = self.block_history.read().unwrap();
+        (block_history.count(), block_history.page(page, page_size))
+    }
+
+    pub fn target_difficulty(&self) -> u64 {
+        self.target_difficulty.load(Ordering::SeqCst)
+    }
+
+    pub fn block_height(&self) -> u64 {
+        self.block_height.load(Ordering::SeqCst)
+    }
+
+    pub fn template_fetch_latency(&self) -> Latency {
+        *self.template_fetch_latency.read().unwrap()
+    }
+
+    pub fn submit_latency(&self) -> Latency {
+        *self.submit_latency.read().unwrap()
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub fn invalid_solutions(&self) -> BTreeMap<u32, u64> {
+        self.device_stats
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(device_index, counters)| (*device_index, counters.stats.invalid_solutions))
+            .collect()
+    }
+
+    pub fn device_stats(&self) -> BTreeMap<u32, DeviceStats> {
+        let now = Instant::now();
+        self.device_stats
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(device_index, counters)| {
+                let mut stats = counters.stats.clone();
+                stats.hashrate = counters.hashrate(now);
+                (*device_index, stats)
+            })
+            .collect()
+    }
+
+    pub fn device_hashrate(&self, device_index: u32) -> RollingHashrate {
+        self.device_stats
+            .read()
+            .unwrap()
+            .get(&device_index)
+            .map(|counters| counters.hashrate(Instant::now()))
+            .unwrap_or_default()
+    }
+
+    /// Rolling hashrates summed across all devices
+    pub fn hashrate(&self) -> RollingHashrate {
+        let now = Instant::now();
+        let mut total = RollingHashrate::default();
+        for counters in self.device_stats.read().unwrap().values() {
+            total.add(&counters.hashrate(now));
+        }
+        total
+    }
+
+    pub fn thermal(&self) -> BTreeMap<u32, ThermalReading> {
+        self.thermal.read().unwrap().clone()
+    }
+
+    pub fn policy(&self) -> PolicyDecision {
+        self.policy.read().unwrap().clone()
+    }
+
+    pub fn devices(&self) -> BTreeMap<u32, DeviceStatus> {
+        self.devices.read().unwrap().clone()
+    }
+
+    pub fn is_device_healthy(&self, device_index: u32) -> bool {
+        !self.unhealthy_devices.read().unwrap().contains(&device_index)
+    }
+
+    pub fn unhealthy_devices(&self) -> Vec<u32> {
+        self.unhealthy_devices.read().unwrap().iter().copied().collect()
+    }
+
+    fn update_device_stats<F: FnOnce(&mut DeviceCounters)>(&self, device_index: u32, f: F) {
+        let mut lock = self.device_stats.write().unwrap();
+        f(lock.entry(device_index).or_insert_with(DeviceCounters::new));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(secs: f64) -> Duration {
+        Duration::from_secs_f64(secs)
+    }
+
+    #[test]
+    fn samples_within_a_second_are_merged() {
+        let start = Instant::now();
+        let mut counters = DeviceCounters::new();
+        counters.record(100, start);
+        counters.record(50, start + secs(0.5));
+        assert_eq!(counters.samples, [(start, 150)]);
+        counters.record(10, start + secs(1.5));
+        assert_eq!(counters.samples, [(start, 150), (start + secs(1.5), 10)]);
+        assert_eq!(counters.stats.hashes, 160);
+    }
+
+    #[test]
+    fn samples_older_than_the_longest_window_are_dropped() {
+        let start = Instant::now();
+        let mut counters = DeviceCounters::new();
+        counters.record(100, start);
+        counters.record(100, start + MAX_WINDOW);
+        assert_eq!(counters.samples.len(), 2);
+        counters.record(100, start + MAX_WINDOW + secs(1.0));
+        assert_eq!(counters.samples.len(), 2);
+        assert_eq!(counters.samples.front(), Some(&(start + MAX_WINDOW, 100)));
+        // Dropped samples still count towards the total
+        assert_eq!(counters.stats.hashes, 300);
+    }
+
+    #[test]
+    fn windows_are_shortened_to_the_session() {
+        let start = Instant::now();
+        let mut counters = DeviceCounters::new();
+        counters.record(1000, start);
+        let hashrate = counters.hashrate(start + secs(2.0));
+        assert_eq!(hashrate.last_10s, 500);
+        assert_eq!(hashrate.last_60s, 500);
+        assert_eq!(hashrate.last_15m, 500);
+        assert_eq!(hashrate.session_average, 500);
+    }
+
+    #[test]
+    fn steady_hashrate_fills_every_window() {
+        let start = Instant::now();
+        let mut counters = DeviceCounters::new();
+        for second in 0..=120 {
+            counters.record(100, start + secs(f64::from(second)));
+        }
+        let hashrate = counters.hashrate(start + secs(120.5));
+        assert_eq!(hashrate.last_10s, 100);
+        assert_eq!(hashrate.last_60s, 100);
+        assert_eq!(hashrate.last_15m, 100);
+        assert_eq!(hashrate.session_average, 100);
+    }
+
+    #[test]
+    fn session_average_includes_idle_time() {
+        let start = Instant::now();
+        let mut counters = DeviceCounters::new();
+        counters.record(1000, start);
+        let hashrate = counters.hashrate(start + secs(100.0));
+        assert_eq!(hashrate.last_10s, 0);
+        assert_eq!(hashrate.last_60s, 0);
+        assert_eq!(hashrate.last_15m, 10);
+        assert_eq!(hashrate.session_average, 10);
+    }
+
+    #[test]
+    fn no_hashrate_before_the_first_sample() {
+        let counters = DeviceCounters::new();
+        let hashrate = counters.hashrate(Instant::now());
+        assert_eq!(hashrate.last_10s, 0);
+        assert_eq!(hashrate.session_average, 0);
+        assert_eq!(per_second(100, Duration::ZERO), 0);
+    }
 }
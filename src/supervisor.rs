@@ -0,0 +1,172 @@
+use std::{
+    any::Any,
+    cmp,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use tari_shutdown::ShutdownSignal;
+
+use crate::{stats_store::StatsStore, watchdog::Watchdog, LOG_TARGET, SHUTDOWN_POLL_INTERVAL};
+
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// A worker that ran at least this long before failing starts counting consecutive failures from zero again
+const HEALTHY_RUN: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceState {
+    Running,
+    Restarting,
+    Disabled,
+    Stopped,
+}
+
+/// Supervisor view of a device worker
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceStatus {
+    pub state: DeviceState,
+    pub restarts: u64,
+    pub last_error: Option<String>,
+}
+
+/// Restarts a failed device worker after an exponential backoff, and disables the device after
+/// `max_consecutive_failures` failures in a row. A worker stuck in a hung batch counts as failed and is left behind in
+/// its own thread.
+pub struct Supervisor {
+    device_index: u32,
+    max_consecutive_failures: u32,
+    base_backoff: Duration,
+    stats_store: Arc<StatsStore>,
+    watchdog: Arc<Watchdog>,
+    shutdown_signal: ShutdownSignal,
+}
+
+impl Supervisor {
+    pub fn new(
+        device_index: u32,
+        max_consecutive_failures: u32,
+        base_backoff: Duration,
+        stats_store: Arc<StatsStore>,
+        watchdog: Arc<Watchdog>,
+        shutdown_signal: ShutdownSignal,
+    ) -> Self {
+        Self {
+            device_index,
+            max_consecutive_failures: cmp::max(max_consecutive_failures, 1),
+            base_backoff,
+            stats_store,
+            watchdog,
+            shutdown_signal,
+        }
+    }
+
+    /// Runs `worker` until it returns `Ok`, which it does on shutdown, or until the device is disabled. Every run
+    /// happens on a new thread with a fresh watchdog generation, which is passed to `worker`, and creates a fresh
+    /// context and function, so a restart starts from a clean device state. Returns the final state.
+    pub fn run<F>(&self, worker: F) -> DeviceState
+    where F: Fn(u64) -> Result<(), anyhow::Error> + Clone + Send + 'static {
+        let mut restarts = 0;
+        let mut consecutive_failures = 0;
+        let mut last_error = None;
+        loop {
+            self.update(DeviceState::Running, restarts, &last_error);
+            let started = Instant::now();
+            let error = match self.run_worker(worker.clone()) {
+                Ok(()) => {
+                    self.update(DeviceState::Stopped, restarts, &last_error);
+                    return DeviceState::Stopped;
+                },
+                Err(error) => error,
+            };
+            if started.elapsed() >= HEALTHY_RUN {
+                consecutive_failures = 0;
+            }
+            consecutive_failures += 1;
+            println!("Device {} failed: {}", self.device_index, error);
+            error!(target: LOG_TARGET,
+                "Device {} failed ({} in a row): {}", self.device_index, consecutive_failures, error
+            );
+            last_error = Some(error);
+
+            if consecutive_failures >= self.max_consecutive_failures {
+                println!("Device {} disabled after {} failures", self.device_index, consecutive_failures);
+                error!(target: LOG_TARGET,
+                    "Device {} disabled after {} consecutive failures", self.device_index, consecutive_failures
+                );
+                self.update(DeviceState::Disabled, restarts, &last_error);
+                return DeviceState::Disabled;
+            }
+
+            let backoff = cmp::min(
+                self.base_backoff.saturating_mul(2u32.saturating_pow(consecutive_failures - 1)),
+                MAX_BACKOFF,
+            );
+            warn!(target: LOG_TARGET, "Device {}: restarting in {:?}", self.device_index, backoff);
+            self.update(DeviceState::Restarting, restarts, &last_error);
+            if !self.sleep(backoff) {
+                self.update(DeviceState::Stopped, restarts, &last_error);
+                return DeviceState::Stopped;
+            }
+            restarts += 1;
+            info!(target: LOG_TARGET, "Device {}: restart {}", self.device_index, restarts);
+        }
+    }
+
+    /// Runs one worker on its own thread until it finishes or the watchdog flags it as hung. A hung worker can't be
+    /// stopped, so it is abandoned; if its batch ever returns, the watchdog tells it that it has been replaced.
+    fn run_worker<F>(&self, worker: F) -> Result<(), String>
+    where F: Fn(u64) -> Result<(), anyhow::Error> + Send + 'static {
+        let generation = self.watchdog.reset(self.device_index);
+        let handle = thread::Builder::new()
+            .name(format!("device-{}-worker-{}", self.device_index, generation))
+            .spawn(move || worker(generation))
+            .map_err(|error| format!("could not start worker thread: {}", error))?;
+        loop {
+            if handle.is_finished() {
+                return match handle.join() {
+                    Ok(Ok(())) => Ok(()),
+                    Ok(Err(error)) => Err(format!("{:?}", error)),
+                    Err(panic) => Err(format!("panic: {}", panic_message(panic.as_ref()))),
+                };
+            }
+            if self.watchdog.is_hung(self.device_index) {
+                return Err("hung in a batch, abandoned the worker".to_string());
+            }
+            thread::sleep(SHUTDOWN_POLL_INTERVAL);
+        }
+    }
+
+    /// Returns false if the miner is shutting down
+    fn sleep(&self, duration: Duration) -> bool {
+        let start = Instant::now();
+        while start.elapsed() < duration {
+            if self.shutdown_signal.is_triggered() {
+                return false;
+            }
+            thread::sleep(cmp::min(SHUTDOWN_POLL_INTERVAL, duration.saturating_sub(start.elapsed())));
+        }
+        !self.shutdown_signal.is_triggered()
+    }
+
+    fn update(&self, state: DeviceState, restarts: u64, last_error: &Option<String>) {
+        self.stats_store.update_device_status(self.device_index, DeviceStatus {
+            state,
+            restarts,
+            last_error: last_error.clone(),
+        });
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
@@ -0,0 +1,274 @@
+use std::{
+    cmp, fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{config_file::ThermalConfig, LOG_TARGET};
+
+/// Idle share added or removed per sensor poll
+const THROTTLE_STEP_PERCENT: u32 = 10;
+const MAX_THROTTLE_PERCENT: u32 = 90;
+
+/// Latest sensor values of a device and how much it is being throttled
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct ThermalReading {
+    pub temperature_celsius: Option<f64>,
+    pub power_watts: Option<f64>,
+    /// Share of time the device is kept idle to cool down
+    pub throttle_percent: u32,
+}
+
+/// hwmon directory of a device, e.g. `/sys/class/drm/card0/device/hwmon/hwmon3`
+#[derive(Debug, Clone)]
+pub struct HwmonSensor {
+    path: PathBuf,
+}
+
+impl HwmonSensor {
+    /// Finds the hwmon directory under `sysfs_root` for `name`, which is either a DRM card (`card0`) or a hwmon device
+    /// (`hwmon3`)
+    pub fn find(sysfs_root: &Path, name: &str) -> Option<Self> {
+        if name.starts_with("hwmon") {
+            let path = sysfs_root.join("class/hwmon").join(name);
+            return path.is_dir().then_some(Self { path });
+        }
+        let hwmon_dir = sysfs_root.join("class/drm").join(name).join("device/hwmon");
+        let mut entries: Vec<PathBuf> = fs::read_dir(hwmon_dir)
+            .ok()?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_dir())
+            .collect();
+        entries.sort();
+        entries.into_iter().next().map(|path| Self { path })
+    }
+
+    /// Temperature of the first sensor, reported by hwmon in millidegrees
+    pub fn temperature_celsius(&self) -> Option<f64> {
+        self.read_value("temp1_input").map(|value| value / 1000.0)
+    }
+
+    /// Average power draw, falling back to the instantaneous value. Both are reported by hwmon in microwatts
+    pub fn power_watts(&self) -> Option<f64> {
+        self.read_value("power1_average")
+            .or_else(|| self.read_value("power1_input"))
+            .map(|value| value / 1_000_000.0)
+    }
+
+    fn read_value(&self, file: &str) -> Option<f64> {
+        fs::read_to_string(self.path.join(file)).ok()?.trim().parse().ok()
+    }
+}
+
+/// Keeps a device under its temperature target by idling it between `mine` calls. The idle share goes up one step per
+/// poll while the device is above the target and comes back down once it is `hysteresis_celsius` below it.
+pub struct ThermalGovernor {
+    device_index: u32,
+    sensor: Option<HwmonSensor>,
+    target_celsius: f64,
+    hysteresis_celsius: f64,
+    poll_interval: Duration,
+    last_poll: Option<Instant>,
+    reading: ThermalReading,
+}
+
+impl ThermalGovernor {
+    pub fn new(config: &ThermalConfig, device_index: u32, device_name: &str) -> Self {
+        let sensor_name = config
+            .sensor(device_index, device_name)
+            .unwrap_or_else(|| format!("card{}", device_index));
+        let sensor = if config.enabled {
+            HwmonSensor::find(&config.sysfs_root, &sensor_name)
+        } else {
+            None
+        };
+        match &sensor {
+            Some(sensor) => info!(target: LOG_TARGET,
+                "Device {}: thermal sensor {}, target {}°C", device_index, sensor.path.display(), config.target_celsius
+            ),
+            None if config.enabled => warn!(target: LOG_TARGET,
+                "Device {}: no thermal sensor found for {} under {}, thermal throttling disabled",
+                device_index, sensor_name, config.sysfs_root.display()
+            ),
+            None => {},
+        }
+        Self {
+            device_index,
+            sensor,
+            target_celsius: config.target_celsius,
+            hysteresis_celsius: config.hysteresis_celsius,
+            poll_interval: Duration::from_millis(config.poll_interval_ms),
+            last_poll: None,
+            reading: ThermalReading::default(),
+        }
+    }
+
+    /// Reads the sensors if the poll interval has passed and adjusts the throttle. Returns the new reading if the
+    /// sensors were read.
+    pub fn poll(&mut self) -> Option<ThermalReading> {
+        let sensor = self.sensor.as_ref()?;
+        if self.last_poll.is_some_and(|last_poll| last_poll.elapsed() < self.poll_interval) {
+            return None;
+        }
+        self.last_poll = Some(Instant::now());
+        self.reading.temperature_celsius = sensor.temperature_celsius();
+        self.reading.power_watts = sensor.power_watts();
+
+        let previous_throttle = self.reading.throttle_percent;
+        if let Some(temperature) = self.reading.temperature_celsius {
+            if temperature > self.target_celsius {
+                self.reading.throttle_percent =
+                    cmp::min(self.reading.throttle_percent + THROTTLE_STEP_PERCENT, MAX_THROTTLE_PERCENT);
+            } else if temperature < self.target_celsius - self.hysteresis_celsius {
+                self.reading.throttle_percent = self.reading.throttle_percent.saturating_sub(THROTTLE_STEP_PERCENT);
+            }
+        }
+        if self.reading.throttle_percent != previous_throttle {
+            println!(
+                "Device {}: {:.1}°C, throttle {}%",
+                self.device_index,
+                self.reading.temperature_celsius.unwrap_or_default(),
+                self.reading.throttle_percent
+            );
+            info!(target: LOG_TARGET,
+                "Device {}: temperature {:?}°C, target {}°C, throttle {}% -> {}%",
+                self.device_index, self.reading.temperature_celsius, self.target_celsius, previous_throttle,
+                self.reading.throttle_percent
+            );
+        }
+        Some(self.reading)
+    }
+
+    /// Idle time after a `mine` call that took `kernel_time`, so the device is busy `100 - throttle_percent`% of the
+    /// time
+    pub fn pause(&self, kernel_time: Duration) -> Duration {
+        let throttle = self.reading.throttle_percent;
+        if throttle == 0 {
+            return Duration::ZERO;
+        }
+        kernel_time * throttle / (100 - throttle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, env, process};
+
+    use super::*;
+
+    /// sysfs tree under the temp directory with a single card, removed on drop
+    struct FakeSysfs {
+        root: PathBuf,
+    }
+
+    impl FakeSysfs {
+        fn new(test: &str) -> Self {
+            let root = env::temp_dir().join(format!("glytex-thermal-{}-{}", test, process::id()));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(root.join("class/drm/card1/device/hwmon/hwmon4")).unwrap();
+            fs::create_dir_all(root.join("class/hwmon/hwmon4")).unwrap();
+            Self { root }
+        }
+
+        fn card_hwmon(&self) -> PathBuf {
+            self.root.join("class/drm/card1/device/hwmon/hwmon4")
+        }
+
+        fn write(&self, file: &str, value: &str) {
+            fs::write(self.card_hwmon().join(file), value).unwrap();
+        }
+
+        fn config(&self) -> ThermalConfig {
+            ThermalConfig {
+                enabled: true,
+                sysfs_root: self.root.clone(),
+                target_celsius: 80.0,
+                hysteresis_celsius: 5.0,
+                poll_interval_ms: 0,
+                sensors: BTreeMap::from([("1".to_string(), "card1".to_string())]),
+            }
+        }
+    }
+
+    impl Drop for FakeSysfs {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    #[test]
+    fn finds_sensor_by_card_and_hwmon_name() {
+        let sysfs = FakeSysfs::new("find");
+        let card = HwmonSensor::find(&sysfs.root, "card1").unwrap();
+        assert_eq!(card.path, sysfs.card_hwmon());
+        let hwmon = HwmonSensor::find(&sysfs.root, "hwmon4").unwrap();
+        assert_eq!(hwmon.path, sysfs.root.join("class/hwmon/hwmon4"));
+        assert!(HwmonSensor::find(&sysfs.root, "card0").is_none());
+        assert!(HwmonSensor::find(&sysfs.root, "hwmon0").is_none());
+    }
+
+    #[test]
+    fn parses_millidegrees_and_microwatts() {
+        let sysfs = FakeSysfs::new("parse");
+        let sensor = HwmonSensor::find(&sysfs.root, "card1").unwrap();
+        assert_eq!(sensor.temperature_celsius(), None);
+        assert_eq!(sensor.power_watts(), None);
+
+        sysfs.write("temp1_input", "65500\n");
+        sysfs.write("power1_input", "180000000\n");
+        assert_eq!(sensor.temperature_celsius(), Some(65.5));
+        assert_eq!(sensor.power_watts(), Some(180.0));
+
+        sysfs.write("power1_average", "150250000\n");
+        assert_eq!(sensor.power_watts(), Some(150.25));
+    }
+
+    #[test]
+    fn throttles_above_target_and_eases_below_hysteresis() {
+        let sysfs = FakeSysfs::new("throttle");
+        let mut governor = ThermalGovernor::new(&sysfs.config(), 1, "Test GPU");
+        let mut poll = |millidegrees: &str| {
+            sysfs.write("temp1_input", millidegrees);
+            governor.poll().unwrap().throttle_percent
+        };
+
+        assert_eq!(poll("70000"), 0);
+        assert_eq!(poll("85000"), 10);
+        assert_eq!(poll("85000"), 20);
+        // Between target - hysteresis and target the throttle holds
+        assert_eq!(poll("80000"), 20);
+        assert_eq!(poll("76000"), 20);
+        assert_eq!(poll("74000"), 10);
+        assert_eq!(poll("74000"), 0);
+        assert_eq!(poll("74000"), 0);
+    }
+
+    #[test]
+    fn throttle_is_capped() {
+        let sysfs = FakeSysfs::new("cap");
+        sysfs.write("temp1_input", "95000");
+        let mut governor = ThermalGovernor::new(&sysfs.config(), 1, "Test GPU");
+        for _ in 0..20 {
+            governor.poll();
+        }
+        assert_eq!(governor.poll().unwrap().throttle_percent, MAX_THROTTLE_PERCENT);
+        assert_eq!(
+            governor.pause(Duration::from_millis(100)),
+            Duration::from_millis(100) * MAX_THROTTLE_PERCENT / (100 - MAX_THROTTLE_PERCENT)
+        );
+    }
+
+    #[test]
+    fn disabled_governor_never_throttles() {
+        let sysfs = FakeSysfs::new("disabled");
+        sysfs.write("temp1_input", "95000");
+        let mut config = sysfs.config();
+        config.enabled = false;
+        let mut governor = ThermalGovernor::new(&config, 1, "Test GPU");
+        assert!(governor.poll().is_none());
+        assert_eq!(governor.pause(Duration::from_millis(100)), Duration::ZERO);
+    }
+}
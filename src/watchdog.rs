@@ -0,0 +1,199 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::{error, info};
+use tari_shutdown::ShutdownSignal;
+
+use crate::{stats_store::StatsStore, LOG_TARGET};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// Weight of the latest batch in the usual batch duration
+const SMOOTHING: f64 = 0.1;
+
+#[derive(Default)]
+struct DeviceWatch {
+    /// Bumped for every worker run, so a worker abandoned by its supervisor can tell it has been replaced
+    generation: u64,
+    batch_started: Option<Instant>,
+    usual_duration: Option<Duration>,
+    hung: bool,
+    /// Whether the current generation has completed a batch, which is what makes a hung device healthy again
+    progressed: bool,
+}
+
+/// Notices `mine` batches that take far longer than usual, which usually means the driver hung. A hung device is
+/// reported unhealthy and its supervisor abandons the stuck worker for a fresh one. If the hung batch ever returns, the
+/// old worker learns from `batch_completed` that it was replaced.
+pub struct Watchdog {
+    timeout_multiplier: f64,
+    min_timeout: Duration,
+    devices: Mutex<BTreeMap<u32, DeviceWatch>>,
+}
+
+impl Watchdog {
+    pub fn new(timeout_multiplier: f64, min_timeout: Duration) -> Self {
+        Self {
+            timeout_multiplier: timeout_multiplier.max(1.0),
+            min_timeout,
+            devices: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Starts watching a new worker run of the device, forgetting any batch a previous run left behind, and returns
+    /// the generation the run passes to `batch_started` and `batch_completed`
+    pub fn reset(&self, device_index: u32) -> u64 {
+        let mut devices = self.devices.lock().unwrap();
+        let watch = devices.entry(device_index).or_default();
+        watch.generation += 1;
+        watch.batch_started = None;
+        watch.hung = false;
+        watch.progressed = false;
+        watch.generation
+    }
+
+    pub fn batch_started(&self, device_index: u32, generation: u64) {
+        let mut devices = self.devices.lock().unwrap();
+        let watch = devices.entry(device_index).or_default();
+        if watch.generation == generation {
+            watch.batch_started = Some(Instant::now());
+        }
+    }
+
+    /// Records a finished batch. Returns true if the batch had been flagged as hung or the worker run has been
+    /// replaced, in which case the caller should stop.
+    pub fn batch_completed(&self, device_index: u32, generation: u64) -> bool {
+        let mut devices = self.devices.lock().unwrap();
+        let watch = devices.entry(device_index).or_default();
+        if watch.generation != generation {
+            return true;
+        }
+        let Some(batch_started) = watch.batch_started.take() else {
+            return false;
+        };
+        let duration = batch_started.elapsed();
+        if watch.hung {
+            watch.hung = false;
+            return true;
+        }
+        watch.progressed = true;
+        watch.usual_duration = Some(match watch.usual_duration {
+            Some(usual_duration) => usual_duration.mul_f64(1.0 - SMOOTHING) + duration.mul_f64(SMOOTHING),
+            None => duration,
+        });
+        false
+    }
+
+    pub fn is_hung(&self, device_index: u32) -> bool {
+        let devices = self.devices.lock().unwrap();
+        devices.get(&device_index).is_some_and(|watch| watch.hung)
+    }
+
+    /// Checks running batches until shutdown, marking devices whose batch overran as unhealthy in `stats_store`
+    pub fn run(&self, stats_store: Arc<StatsStore>, shutdown_signal: ShutdownSignal) {
+        while !shutdown_signal.is_triggered() {
+            thread::sleep(CHECK_INTERVAL);
+            self.check(&stats_store);
+        }
+    }
+
+    fn check(&self, stats_store: &StatsStore) {
+        let mut devices = self.devices.lock().unwrap();
+        for (device_index, watch) in devices.iter_mut() {
+            let overran = match watch.batch_started {
+                Some(batch_started) => batch_started.elapsed() > self.timeout(watch.usual_duration),
+                None => false,
+            };
+            if overran && !watch.hung {
+                watch.hung = true;
+                println!("Device {} looks hung", device_index);
+                error!(target: LOG_TARGET,
+                    "Device {}: batch running for {:?}, usually {:?}, marking it unhealthy",
+                    device_index,
+                    watch.batch_started.map(|batch_started| batch_started.elapsed()),
+                    watch.usual_duration
+                );
+                stats_store.set_device_healthy(*device_index, false);
+            } else if !watch.hung && watch.progressed && !stats_store.is_device_healthy(*device_index) {
+                info!(target: LOG_TARGET, "Device {} is healthy again", device_index);
+                stats_store.set_device_healthy(*device_index, true);
+            }
+        }
+    }
+
+    fn timeout(&self, usual_duration: Option<Duration>) -> Duration {
+        match usual_duration {
+            Some(usual_duration) => usual_duration.mul_f64(self.timeout_multiplier).max(self.min_timeout),
+            None => self.min_timeout,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reset_forgets_a_batch_left_behind() {
+        let watchdog = Watchdog::new(2.0, Duration::from_secs(30));
+        let generation = watchdog.reset(0);
+        // The worker panicked mid-batch, so batch_completed was never called
+        watchdog.batch_started(0, generation);
+        let generation = watchdog.reset(0);
+        assert!(!watchdog.batch_completed(0, generation));
+        watchdog.batch_started(0, generation);
+        assert!(!watchdog.batch_completed(0, generation));
+    }
+
+    #[test]
+    fn replaced_worker_is_told_to_stop() {
+        let watchdog = Watchdog::new(2.0, Duration::from_secs(30));
+        let abandoned = watchdog.reset(0);
+        watchdog.batch_started(0, abandoned);
+        watchdog.devices.lock().unwrap().get_mut(&0).unwrap().hung = true;
+        assert!(watchdog.is_hung(0));
+
+        let current = watchdog.reset(0);
+        assert!(!watchdog.is_hung(0));
+        watchdog.batch_started(0, current);
+        // The hung batch finally returns, and must not disturb the new worker's batch
+        assert!(watchdog.batch_completed(0, abandoned));
+        watchdog.batch_started(0, abandoned);
+        assert!(!watchdog.batch_completed(0, current));
+    }
+
+    #[test]
+    fn hung_batch_is_reported_on_completion() {
+        let watchdog = Watchdog::new(2.0, Duration::from_secs(30));
+        let generation = watchdog.reset(3);
+        watchdog.batch_started(3, generation);
+        watchdog.devices.lock().unwrap().get_mut(&3).unwrap().hung = true;
+        assert!(watchdog.batch_completed(3, generation));
+        assert!(!watchdog.is_hung(3));
+    }
+
+    #[test]
+    fn hung_device_is_healthy_again_only_after_the_new_worker_completes_a_batch() {
+        let stats_store = StatsStore::new();
+        let watchdog = Watchdog::new(2.0, Duration::ZERO);
+        let abandoned = watchdog.reset(0);
+        watchdog.batch_started(0, abandoned);
+        thread::sleep(Duration::from_millis(5));
+        watchdog.check(&stats_store);
+        assert!(watchdog.is_hung(0));
+        assert!(!stats_store.is_device_healthy(0));
+
+        // The new worker may itself hang before its first batch, e.g. creating its context
+        let current = watchdog.reset(0);
+        watchdog.check(&stats_store);
+        assert!(!stats_store.is_device_healthy(0));
+
+        watchdog.batch_started(0, current);
+        assert!(!watchdog.batch_completed(0, current));
+        watchdog.check(&stats_store);
+        assert!(stats_store.is_device_healthy(0));
+    }
+}
@@ -0,0 +1,15 @@
+use std::sync::Arc;
+
+use tari_shutdown::ShutdownSignal;
+
+use crate::{mining_control::MiningControl, mining_policy::MiningPolicy, stats_store::StatsStore, watchdog::Watchdog};
+
+/// Handles shared by the workers of every device
+#[derive(Clone)]
+pub struct WorkerContext {
+    pub stats_store: Arc<StatsStore>,
+    pub mining_control: Arc<MiningControl>,
+    pub mining_policy: Arc<MiningPolicy>,
+    pub watchdog: Arc<Watchdog>,
+    pub shutdown_signal: ShutdownSignal,
+}